@@ -0,0 +1,349 @@
+use std::io::{Cursor, Error, Read, Result, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    bytecode::{Signature, Type},
+    debug::DebugInfo,
+};
+
+pub const MAGIC: [u8; 4] = *b"ROTH";
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Size of magic, version, reserved field, section count and checksum
+const HEADER_SIZE: usize = 20;
+/// Size of kind, reserved field, offset and length
+const SECTION_ENTRY_SIZE: usize = 24;
+
+pub const SECTION_CONSTANTS: u32 = 0x1;
+pub const SECTION_CODE: u32 = 0x2;
+pub const SECTION_DEBUG: u32 = 0x3;
+pub const SECTION_SYMBOLS: u32 = 0x4;
+pub const SECTION_METADATA: u32 = 0x5;
+pub const SECTION_IMPORTS: u32 = 0x6;
+pub const SECTION_DATA: u32 = 0x7;
+
+/// A compiled program.
+///
+/// The file starts with a header holding the magic, the format version, the amount of sections and
+/// a checksum over everything following the header. The header is followed by the section table
+/// and the sections it points to. Sections of unknown kinds are skipped when reading.
+pub struct Binary {
+    pub constants: Vec<String>,
+    /// Types of the global variables, referenced by their index
+    pub globals: Vec<Type>,
+    pub code: Vec<u8>,
+    pub debug_info: Option<DebugInfo>,
+    /// Names of words with their offsets
+    pub symbols: Vec<(String, u64)>,
+    pub metadata: Vec<(String, String)>,
+    /// Native words used by the program, referenced by their index
+    pub imports: Vec<(String, Signature)>,
+}
+
+impl Binary {
+    pub fn write(&self, write: &mut impl Write) -> Result<()> {
+        let mut sections = Vec::new();
+        let mut constants = Vec::new();
+        write_strings(&mut constants, &self.constants)?;
+        sections.push((SECTION_CONSTANTS, constants));
+        sections.push((SECTION_CODE, self.code.clone()));
+        if !self.globals.is_empty() {
+            let mut data = Vec::new();
+            data.write_u64::<LittleEndian>(self.globals.len() as _)?;
+            for type_ in &self.globals {
+                data.write_u8(type_.tag())?;
+            }
+            sections.push((SECTION_DATA, data));
+        }
+        if let Some(debug_info) = &self.debug_info {
+            let mut debug = Vec::new();
+            debug_info.write(&mut debug)?;
+            sections.push((SECTION_DEBUG, debug));
+        }
+        if !self.symbols.is_empty() {
+            let mut symbols = Vec::new();
+            symbols.write_u64::<LittleEndian>(self.symbols.len() as _)?;
+            for (name, offset) in &self.symbols {
+                write_string(&mut symbols, name)?;
+                symbols.write_u64::<LittleEndian>(*offset)?;
+            }
+            sections.push((SECTION_SYMBOLS, symbols));
+        }
+        if !self.metadata.is_empty() {
+            let mut metadata = Vec::new();
+            metadata.write_u64::<LittleEndian>(self.metadata.len() as _)?;
+            for (key, value) in &self.metadata {
+                write_string(&mut metadata, key)?;
+                write_string(&mut metadata, value)?;
+            }
+            sections.push((SECTION_METADATA, metadata));
+        }
+        if !self.imports.is_empty() {
+            let mut imports = Vec::new();
+            imports.write_u64::<LittleEndian>(self.imports.len() as _)?;
+            for (name, signature) in &self.imports {
+                write_string(&mut imports, name)?;
+                write_types(&mut imports, &signature.inputs)?;
+                write_types(&mut imports, &signature.outputs)?;
+            }
+            sections.push((SECTION_IMPORTS, imports));
+        }
+        let mut body = Vec::new();
+        let mut offset = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+        for (kind, section) in &sections {
+            body.write_u32::<LittleEndian>(*kind)?;
+            body.write_u32::<LittleEndian>(0)?;
+            body.write_u64::<LittleEndian>(offset as _)?;
+            body.write_u64::<LittleEndian>(section.len() as _)?;
+            offset += section.len();
+        }
+        for (_, section) in &sections {
+            body.write_all(section)?;
+        }
+        write.write_all(&MAGIC)?;
+        write.write_u16::<LittleEndian>(FORMAT_VERSION)?;
+        write.write_u16::<LittleEndian>(0)?;
+        write.write_u32::<LittleEndian>(sections.len() as _)?;
+        write.write_u64::<LittleEndian>(checksum(&body))?;
+        write.write_all(&body)
+    }
+
+    pub fn read(read: &mut impl Read) -> Result<Self> {
+        let mut bytes = Vec::new();
+        read.read_to_end(&mut bytes)?;
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err(Error::other("Not a roth binary"));
+        }
+        let mut header = Cursor::new(&bytes[4..HEADER_SIZE]);
+        let version = header.read_u16::<LittleEndian>()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::other(format!(
+                "Unsupported format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        header.read_u16::<LittleEndian>()?;
+        let section_count = header.read_u32::<LittleEndian>()? as usize;
+        if checksum(&bytes[HEADER_SIZE..]) != header.read_u64::<LittleEndian>()? {
+            return Err(Error::other("Checksum mismatch, the binary is corrupted"));
+        }
+        if section_count > (bytes.len() - HEADER_SIZE) / SECTION_ENTRY_SIZE {
+            return Err(Error::other("Section table exceeds the end of the binary"));
+        }
+        let mut table = Cursor::new(&bytes[HEADER_SIZE..]);
+        let mut binary = Binary {
+            constants: Vec::new(),
+            globals: Vec::new(),
+            code: Vec::new(),
+            debug_info: None,
+            symbols: Vec::new(),
+            metadata: Vec::new(),
+            imports: Vec::new(),
+        };
+        let mut has_constants = false;
+        let mut has_code = false;
+        for _ in 0..section_count {
+            let kind = table.read_u32::<LittleEndian>()?;
+            table.read_u32::<LittleEndian>()?;
+            let offset = table.read_u64::<LittleEndian>()? as usize;
+            let len = table.read_u64::<LittleEndian>()? as usize;
+            let Some(section) = offset
+                .checked_add(len)
+                .and_then(|end| bytes.get(offset..end))
+            else {
+                return Err(Error::other(format!(
+                    "Section 0x{kind:X} exceeds the end of the binary"
+                )));
+            };
+            let mut section = Cursor::new(section);
+            match kind {
+                SECTION_CONSTANTS => {
+                    binary.constants = read_strings(&mut section)?;
+                    has_constants = true;
+                }
+                SECTION_CODE => {
+                    binary.code = section.into_inner().to_vec();
+                    has_code = true;
+                }
+                SECTION_DATA => {
+                    let len = section.read_u64::<LittleEndian>()?;
+                    for _ in 0..len {
+                        binary.globals.push(read_type(&mut section)?);
+                    }
+                }
+                SECTION_DEBUG => binary.debug_info = Some(DebugInfo::read(&mut section)?),
+                SECTION_SYMBOLS => {
+                    let len = section.read_u64::<LittleEndian>()?;
+                    for _ in 0..len {
+                        let name = read_string(&mut section)?;
+                        binary
+                            .symbols
+                            .push((name, section.read_u64::<LittleEndian>()?));
+                    }
+                }
+                SECTION_METADATA => {
+                    let len = section.read_u64::<LittleEndian>()?;
+                    for _ in 0..len {
+                        let key = read_string(&mut section)?;
+                        binary.metadata.push((key, read_string(&mut section)?));
+                    }
+                }
+                SECTION_IMPORTS => {
+                    let len = section.read_u64::<LittleEndian>()?;
+                    for _ in 0..len {
+                        let name = read_string(&mut section)?;
+                        let signature = Signature {
+                            inputs: read_types(&mut section)?,
+                            outputs: read_types(&mut section)?,
+                        };
+                        binary.imports.push((name, signature));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !has_constants || !has_code {
+            return Err(Error::other(
+                "Binary is missing the constant or code section",
+            ));
+        }
+        Ok(binary)
+    }
+}
+
+/// 64-bit FNV-1a hash
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash = 0xCBF29CE484222325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001B3);
+    }
+    hash
+}
+
+pub(crate) fn write_string(write: &mut impl Write, string: &str) -> Result<()> {
+    write.write_u64::<LittleEndian>(string.len() as _)?;
+    write.write_all(string.as_bytes())
+}
+
+pub(crate) fn read_string(read: &mut impl Read) -> Result<String> {
+    let len = read.read_u64::<LittleEndian>()?;
+    let mut buf = String::new();
+    read.take(len).read_to_string(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(Error::other("Unexpected end of string"));
+    }
+    Ok(buf)
+}
+
+fn write_strings(write: &mut impl Write, strings: &[String]) -> Result<()> {
+    write.write_u64::<LittleEndian>(strings.len() as _)?;
+    for string in strings {
+        write_string(write, string)?;
+    }
+    Ok(())
+}
+
+fn read_strings(read: &mut impl Read) -> Result<Vec<String>> {
+    let len = read.read_u64::<LittleEndian>()?;
+    let mut strings = Vec::new();
+    for _ in 0..len {
+        strings.push(read_string(read)?);
+    }
+    Ok(strings)
+}
+
+fn write_types(write: &mut impl Write, types: &[Type]) -> Result<()> {
+    write.write_u8(types.len() as _)?;
+    for type_ in types {
+        write.write_u8(type_.tag())?;
+    }
+    Ok(())
+}
+
+fn read_types(read: &mut impl Read) -> Result<Vec<Type>> {
+    let len = read.read_u8()?;
+    let mut types = Vec::new();
+    for _ in 0..len {
+        types.push(read_type(read)?);
+    }
+    Ok(types)
+}
+
+fn read_type(read: &mut impl Read) -> Result<Type> {
+    let tag = read.read_u8()?;
+    Type::from_tag(tag).ok_or_else(|| Error::other(format!("Unknown type 0x{tag:02X}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, parser, Flags};
+
+    const SOURCE: &str =
+        "var total: int\nfn add ( int -- ) total + total! end\n\"a\" strlen add total exit";
+
+    fn compile() -> Binary {
+        let signature = Signature {
+            inputs: vec![Type::String],
+            outputs: vec![Type::Int],
+        };
+        let natives = [("strlen".to_string(), signature)];
+        let pre_binary = parser::parse(SOURCE, &Flags::default(), &natives).unwrap();
+        compiler::compile(&pre_binary, Some("a.roth"))
+    }
+
+    fn write(binary: &Binary) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        binary.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read_error(bytes: &[u8]) -> String {
+        match Binary::read(&mut Cursor::new(bytes)) {
+            Ok(_) => panic!("binary was read"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let binary = compile();
+        let bytes = write(&binary);
+        let read = Binary::read(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(read.code, binary.code);
+        assert_eq!(read.constants, binary.constants);
+        assert_eq!(read.globals, [Type::Int]);
+        assert_eq!(read.imports, binary.imports);
+        assert_eq!(read.symbols, binary.symbols);
+        assert_eq!(read.metadata, binary.metadata);
+        assert_eq!(read.debug_info.as_ref().unwrap().file, "a.roth");
+        assert_eq!(write(&read), bytes);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let bytes = write(&compile());
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(read_error(&magic), "Not a roth binary");
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert!(read_error(&version).starts_with("Unsupported format version 2"));
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(read_error(&corrupted).starts_with("Checksum mismatch"));
+    }
+
+    #[test]
+    fn rejects_truncated_section_table() {
+        let mut bytes = write(&compile());
+        bytes.truncate(HEADER_SIZE + SECTION_ENTRY_SIZE + 4);
+        let checksum = checksum(&bytes[HEADER_SIZE..]);
+        bytes[12..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            read_error(&bytes),
+            "Section table exceeds the end of the binary"
+        );
+    }
+}
@@ -3,10 +3,16 @@
 pub const TYPE_I64: u8 = 0x1;
 pub const TYPE_F64: u8 = 0x2;
 pub const TYPE_STR: u8 = 0x3;
+pub const TYPE_ADDR: u8 = 0x4;
+pub const TYPE_BOOL: u8 = 0x5;
+/// Combined with the type of the elements
+pub const TYPE_ARRAY: u8 = 0x10;
 
 const FLAG_I64: u16 = (TYPE_I64 as u16) << 8;
 const FLAG_F64: u16 = (TYPE_F64 as u16) << 8;
 const FLAG_STR: u16 = (TYPE_STR as u16) << 8;
+const FLAG_ADDR: u16 = (TYPE_ADDR as u16) << 8;
+const FLAG_BOOL: u16 = (TYPE_BOOL as u16) << 8;
 
 // Memory operations
 /// Drop top of the stack
@@ -20,18 +26,69 @@ pub const INSN_DUP: u16 = 0x0003;
 pub const INSN_TROT: u16 = 0x0004;
 pub const INSN_DDUP: u16 = 0x0005;
 pub const INSN_TDUP: u16 = 0x0006;
+/// Copy the second element to the top of the stack
+pub const INSN_OVER: u16 = 0x0010;
+/// Drop the second element on the stack
+pub const INSN_NIP: u16 = 0x0011;
+/// Copy the top element below the second element on the stack
+pub const INSN_TUCK: u16 = 0x0012;
+/// Duplicate the top two elements on the stack
+pub const INSN_DUP2: u16 = 0x0013;
+/// Drop the top two elements on the stack
+pub const INSN_DROP2: u16 = 0x0014;
+/// Swap the top two pairs of elements on the stack
+pub const INSN_SWAP2: u16 = 0x0015;
+/// Copy the element at the depth given by the immediate to the top of the stack, 0 is the top
+pub const INSN_PICK: u16 = 0x0016;
+/// Move the element at the depth given by the immediate to the top of the stack, 0 is the top
+pub const INSN_ROLL: u16 = 0x0017;
+/// Push the local at the index given by the immediate in the frame of the current subroutine
+pub const INSN_LOAD_LOCAL: u16 = 0x0018;
+/// Pop the top of the stack into the local at the index given by the immediate
+pub const INSN_STORE_LOCAL: u16 = 0x0019;
+/// Push the global at the index given by the immediate
+pub const INSN_GLOAD: u16 = 0x001A;
+/// Pop the top of the stack into the global at the index given by the immediate
+pub const INSN_GSTORE: u16 = 0x001B;
+
+/// Maximum amount of locals in the frame of a subroutine
+pub const MAX_LOCALS: usize = 1024;
 
 const INSN_PUSH: u16 = 0x0008;
 const INSN_NUMCONV: u16 = 0x0009;
+const INSN_BOOLCONV: u16 = 0x000A;
+const INSN_ADDRCONV: u16 = 0x000B;
+const INSN_STRCONV: u16 = 0x000C;
+const INSN_FLOATCONV: u16 = 0x000D;
 
 /// Push i64 onto stack
 pub const INSN_PUSH_I64: u16 = INSN_PUSH | FLAG_I64;
 /// Push f64 onto stack
 pub const INSN_PUSH_F64: u16 = INSN_PUSH | FLAG_F64;
+/// Push bool onto stack, the immediate is 0 for false and 1 for true
+pub const INSN_PUSH_BOOL: u16 = INSN_PUSH | FLAG_BOOL;
+/// Push code address onto stack
+pub const INSN_PUSH_ADDR: u16 = INSN_PUSH | FLAG_ADDR;
 /// Convert f64 to i64
 pub const INSN_NUMCONV_I64: u16 = INSN_NUMCONV | FLAG_I64;
 /// Convert i64 to f64
 pub const INSN_NUMCONV_F64: u16 = INSN_NUMCONV | FLAG_F64;
+/// Convert i64 to bool, which is true if the integer is not zero
+pub const INSN_NUMCONV_BOOL: u16 = INSN_NUMCONV | FLAG_BOOL;
+/// Convert i64 to code address
+pub const INSN_NUMCONV_ADDR: u16 = INSN_NUMCONV | FLAG_ADDR;
+/// Convert i64 to string
+pub const INSN_NUMCONV_STR: u16 = INSN_NUMCONV | FLAG_STR;
+/// Convert f64 to string
+pub const INSN_FLOATCONV_STR: u16 = INSN_FLOATCONV | FLAG_STR;
+/// Parse string as i64, pushes the number and whether the string was valid
+pub const INSN_STRCONV_I64: u16 = INSN_STRCONV | FLAG_I64;
+/// Parse string as f64, pushes the number and whether the string was valid
+pub const INSN_STRCONV_F64: u16 = INSN_STRCONV | FLAG_F64;
+/// Convert bool to i64
+pub const INSN_BOOLCONV_I64: u16 = INSN_BOOLCONV | FLAG_I64;
+/// Convert code address to i64
+pub const INSN_ADDRCONV_I64: u16 = INSN_ADDRCONV | FLAG_I64;
 
 // System operations
 /// Abort virtual machine
@@ -46,6 +103,8 @@ pub const INSN_PRINTLN: u16 = 0x1003;
 pub const INSN_INPUT: u16 = 0x1004;
 /// Start to remove unused strings
 pub const INSN_GC: u16 = 0x1005;
+/// Call native word, the immediate is the index of the import
+pub const INSN_NATIVE: u16 = 0x1006;
 
 const INSN_PRINT: u16 = 0x1008;
 
@@ -55,18 +114,50 @@ pub const INSN_PRINT_I64: u16 = INSN_PRINT | FLAG_I64;
 pub const INSN_PRINT_F64: u16 = INSN_PRINT | FLAG_F64;
 /// Print string to standard output
 pub const INSN_PRINT_STR: u16 = INSN_PRINT | FLAG_STR;
+/// Print bool to standard output
+pub const INSN_PRINT_BOOL: u16 = INSN_PRINT | FLAG_BOOL;
 
 // Arithmetic operations
 const INSN_ADD: u16 = 0x2000;
 const INSN_SUB: u16 = 0x2001;
 const INSN_MUL: u16 = 0x2002;
 const INSN_DIV: u16 = 0x2003;
+const INSN_ADD_WRAP: u16 = 0x2004;
+const INSN_SUB_WRAP: u16 = 0x2005;
+const INSN_MUL_WRAP: u16 = 0x2006;
+const INSN_DIV_WRAP: u16 = 0x2007;
+const INSN_ADD_SAT: u16 = 0x2008;
+const INSN_SUB_SAT: u16 = 0x2009;
+const INSN_MUL_SAT: u16 = 0x200A;
+const INSN_DIV_SAT: u16 = 0x200B;
+const INSN_MOD: u16 = 0x200C;
+const INSN_NEG: u16 = 0x200D;
+
+// Bitwise operations
+const INSN_AND: u16 = 0x2010;
+const INSN_OR: u16 = 0x2011;
+const INSN_XOR: u16 = 0x2012;
+const INSN_NOT: u16 = 0x2013;
+const INSN_SHL: u16 = 0x2014;
+const INSN_SHR: u16 = 0x2015;
 
 pub const INSN_ADD_I64: u16 = INSN_ADD | FLAG_I64;
 pub const INSN_SUB_I64: u16 = INSN_SUB | FLAG_I64;
 pub const INSN_MUL_I64: u16 = INSN_MUL | FLAG_I64;
 pub const INSN_DIV_I64: u16 = INSN_DIV | FLAG_I64;
 
+/// Integer arithmetic that wraps around on overflow
+pub const INSN_ADD_WRAP_I64: u16 = INSN_ADD_WRAP | FLAG_I64;
+pub const INSN_SUB_WRAP_I64: u16 = INSN_SUB_WRAP | FLAG_I64;
+pub const INSN_MUL_WRAP_I64: u16 = INSN_MUL_WRAP | FLAG_I64;
+pub const INSN_DIV_WRAP_I64: u16 = INSN_DIV_WRAP | FLAG_I64;
+
+/// Integer arithmetic that saturates at the bounds on overflow
+pub const INSN_ADD_SAT_I64: u16 = INSN_ADD_SAT | FLAG_I64;
+pub const INSN_SUB_SAT_I64: u16 = INSN_SUB_SAT | FLAG_I64;
+pub const INSN_MUL_SAT_I64: u16 = INSN_MUL_SAT | FLAG_I64;
+pub const INSN_DIV_SAT_I64: u16 = INSN_DIV_SAT | FLAG_I64;
+
 pub const INSN_ADD_F64: u16 = INSN_ADD | FLAG_F64;
 pub const INSN_SUB_F64: u16 = INSN_SUB | FLAG_F64;
 pub const INSN_MUL_F64: u16 = INSN_MUL | FLAG_F64;
@@ -74,6 +165,28 @@ pub const INSN_DIV_F64: u16 = INSN_DIV | FLAG_F64;
 
 pub const INSN_ADD_STR: u16 = INSN_ADD | FLAG_STR;
 
+/// Remainder of the division, which has the sign of the dividend
+pub const INSN_MOD_I64: u16 = INSN_MOD | FLAG_I64;
+pub const INSN_MOD_F64: u16 = INSN_MOD | FLAG_F64;
+/// Negate the top of the stack
+pub const INSN_NEG_I64: u16 = INSN_NEG | FLAG_I64;
+pub const INSN_NEG_F64: u16 = INSN_NEG | FLAG_F64;
+
+pub const INSN_AND_I64: u16 = INSN_AND | FLAG_I64;
+pub const INSN_OR_I64: u16 = INSN_OR | FLAG_I64;
+pub const INSN_XOR_I64: u16 = INSN_XOR | FLAG_I64;
+/// Invert all bits of the top of the stack
+pub const INSN_NOT_I64: u16 = INSN_NOT | FLAG_I64;
+/// Shift left by the amount on top of the stack
+pub const INSN_SHL_I64: u16 = INSN_SHL | FLAG_I64;
+/// Shift right by the amount on top of the stack, keeping the sign
+pub const INSN_SHR_I64: u16 = INSN_SHR | FLAG_I64;
+
+/// Logical operations
+pub const INSN_AND_BOOL: u16 = INSN_AND | FLAG_BOOL;
+pub const INSN_OR_BOOL: u16 = INSN_OR | FLAG_BOOL;
+pub const INSN_NOT_BOOL: u16 = INSN_NOT | FLAG_BOOL;
+
 // Compare operations
 const INSN_EQ: u16 = 0x3000;
 const INSN_LT: u16 = 0x3001;
@@ -94,16 +207,244 @@ pub const INSN_LE_F64: u16 = INSN_LE | FLAG_F64;
 pub const INSN_GE_F64: u16 = INSN_GE | FLAG_F64;
 
 pub const INSN_EQ_STR: u16 = INSN_EQ | FLAG_STR;
+/// Compare strings lexicographically
+pub const INSN_LT_STR: u16 = INSN_LT | FLAG_STR;
+pub const INSN_GT_STR: u16 = INSN_GT | FLAG_STR;
 
 // Jumps
-/// Jump to address on top of the stack
+/// Jump to code address on top of the stack
 pub const INSN_J: u16 = 0x4000;
-/// Jump to address on top of the stack if the second element on the stack is non-zero
+/// Jump to address on top of the stack if the bool below it is true
 pub const INSN_JNZ: u16 = 0x4001;
-/// Jump to address on top of the stack if the second element on the stack is zero
+/// Jump to address on top of the stack if the bool below it is false
 pub const INSN_JZ: u16 = 0x4002;
-/// Call address on top of the stack
+/// Call address on top of the stack and push the return address onto the return stack
 pub const INSN_CALL: u16 = 0x4003;
+/// Return to the address on top of the return stack
+pub const INSN_RET: u16 = 0x4004;
+
+// String operations, indices and lengths count characters
+const INSN_LEN: u16 = 0x5000;
+const INSN_SUBSTR: u16 = 0x5001;
+const INSN_FIND: u16 = 0x5002;
+const INSN_CHAR_AT: u16 = 0x5003;
+const INSN_SPLIT_FIRST: u16 = 0x5004;
+const INSN_TRIM: u16 = 0x5005;
+const INSN_UPPER: u16 = 0x5006;
+const INSN_LOWER: u16 = 0x5007;
+const INSN_STARTS_WITH: u16 = 0x5008;
+
+/// Number of characters in the string
+pub const INSN_LEN_STR: u16 = INSN_LEN | FLAG_STR;
+/// Take the characters of the string starting at the second integer on the stack, the amount is
+/// the integer on top of the stack
+pub const INSN_SUBSTR_STR: u16 = INSN_SUBSTR | FLAG_STR;
+/// Index of the first occurrence of the string on top of the stack in the second string, -1 if it
+/// does not occur
+pub const INSN_FIND_STR: u16 = INSN_FIND | FLAG_STR;
+/// Character at the index on top of the stack as string
+pub const INSN_CHAR_AT_STR: u16 = INSN_CHAR_AT | FLAG_STR;
+/// Split the second string at the first occurrence of the separator on top of the stack, pushes
+/// both parts and whether the separator was found
+pub const INSN_SPLIT_FIRST_STR: u16 = INSN_SPLIT_FIRST | FLAG_STR;
+/// Remove whitespace at both ends of the string
+pub const INSN_TRIM_STR: u16 = INSN_TRIM | FLAG_STR;
+pub const INSN_UPPER_STR: u16 = INSN_UPPER | FLAG_STR;
+pub const INSN_LOWER_STR: u16 = INSN_LOWER | FLAG_STR;
+/// Whether the second string starts with the string on top of the stack
+pub const INSN_STARTS_WITH_STR: u16 = INSN_STARTS_WITH | FLAG_STR;
+
+// Array operations, the flag is the type of the elements
+const INSN_NEW_ARRAY: u16 = 0x6000;
+const INSN_GET: u16 = 0x6001;
+const INSN_SET: u16 = 0x6002;
+const INSN_ARRAY_LEN: u16 = 0x6003;
+const INSN_APPEND: u16 = 0x6004;
+
+/// Push a new empty array
+pub const INSN_NEW_ARRAY_I64: u16 = INSN_NEW_ARRAY | FLAG_I64;
+pub const INSN_NEW_ARRAY_F64: u16 = INSN_NEW_ARRAY | FLAG_F64;
+pub const INSN_NEW_ARRAY_STR: u16 = INSN_NEW_ARRAY | FLAG_STR;
+pub const INSN_NEW_ARRAY_BOOL: u16 = INSN_NEW_ARRAY | FLAG_BOOL;
+/// Get the element of the array at the index on top of the stack
+pub const INSN_GET_I64: u16 = INSN_GET | FLAG_I64;
+pub const INSN_GET_F64: u16 = INSN_GET | FLAG_F64;
+pub const INSN_GET_STR: u16 = INSN_GET | FLAG_STR;
+pub const INSN_GET_BOOL: u16 = INSN_GET | FLAG_BOOL;
+/// Set the element of the array at the index to the value on top of the stack
+pub const INSN_SET_I64: u16 = INSN_SET | FLAG_I64;
+pub const INSN_SET_F64: u16 = INSN_SET | FLAG_F64;
+pub const INSN_SET_STR: u16 = INSN_SET | FLAG_STR;
+pub const INSN_SET_BOOL: u16 = INSN_SET | FLAG_BOOL;
+/// Number of elements in the array
+pub const INSN_ARRAY_LEN_I64: u16 = INSN_ARRAY_LEN | FLAG_I64;
+pub const INSN_ARRAY_LEN_F64: u16 = INSN_ARRAY_LEN | FLAG_F64;
+pub const INSN_ARRAY_LEN_STR: u16 = INSN_ARRAY_LEN | FLAG_STR;
+pub const INSN_ARRAY_LEN_BOOL: u16 = INSN_ARRAY_LEN | FLAG_BOOL;
+/// Append the value on top of the stack to the array
+pub const INSN_APPEND_I64: u16 = INSN_APPEND | FLAG_I64;
+pub const INSN_APPEND_F64: u16 = INSN_APPEND | FLAG_F64;
+pub const INSN_APPEND_STR: u16 = INSN_APPEND | FLAG_STR;
+pub const INSN_APPEND_BOOL: u16 = INSN_APPEND | FLAG_BOOL;
+
+/// Returns the type of the elements an array operation works on
+pub fn element_type(insn: u16) -> Option<Type> {
+    Type::from_tag((insn >> 8) as u8 & 0x0F)
+}
+
+/// Returns the size of the immediate operand following the instruction
+pub fn operand_size(insn: u16) -> usize {
+    match insn {
+        INSN_PUSH_I64 | INSN_PUSH_F64 | INSN_PUSH_BOOL | INSN_PUSH_ADDR | INSN_NATIVE
+        | INSN_PICK | INSN_ROLL | INSN_LOAD_LOCAL | INSN_STORE_LOCAL | INSN_GLOAD | INSN_GSTORE => {
+            8
+        }
+        _ => 0,
+    }
+}
+
+/// Returns the name of the instruction used in disassembly listings
+pub fn mnemonic(insn: u16) -> Option<&'static str> {
+    Some(match insn {
+        INSN_DROP => "drop",
+        INSN_LOAD => "load",
+        INSN_SWAP => "swap",
+        INSN_DUP => "dup",
+        INSN_TROT => "trot",
+        INSN_DDUP => "ddup",
+        INSN_TDUP => "tdup",
+        INSN_OVER => "over",
+        INSN_NIP => "nip",
+        INSN_TUCK => "tuck",
+        INSN_DUP2 => "dup2",
+        INSN_DROP2 => "drop2",
+        INSN_SWAP2 => "swap2",
+        INSN_PICK => "pick",
+        INSN_ROLL => "roll",
+        INSN_LOAD_LOCAL => "load_local",
+        INSN_STORE_LOCAL => "store_local",
+        INSN_GLOAD => "gload",
+        INSN_GSTORE => "gstore",
+        INSN_PUSH_I64 => "push_i64",
+        INSN_PUSH_F64 => "push_f64",
+        INSN_PUSH_BOOL => "push_bool",
+        INSN_PUSH_ADDR => "push_addr",
+        INSN_NUMCONV_I64 => "numconv_i64",
+        INSN_NUMCONV_F64 => "numconv_f64",
+        INSN_NUMCONV_BOOL => "numconv_bool",
+        INSN_NUMCONV_ADDR => "numconv_addr",
+        INSN_NUMCONV_STR => "numconv_str",
+        INSN_FLOATCONV_STR => "floatconv_str",
+        INSN_STRCONV_I64 => "strconv_i64",
+        INSN_STRCONV_F64 => "strconv_f64",
+        INSN_BOOLCONV_I64 => "boolconv_i64",
+        INSN_ADDRCONV_I64 => "addrconv_i64",
+        INSN_ABORT => "abort",
+        INSN_EXIT => "exit",
+        INSN_PANIC => "panic",
+        INSN_PRINTLN => "println",
+        INSN_INPUT => "input",
+        INSN_GC => "gc",
+        INSN_NATIVE => "native",
+        INSN_PRINT_I64 => "print_i64",
+        INSN_PRINT_F64 => "print_f64",
+        INSN_PRINT_STR => "print_str",
+        INSN_PRINT_BOOL => "print_bool",
+        INSN_ADD_I64 => "add_i64",
+        INSN_SUB_I64 => "sub_i64",
+        INSN_MUL_I64 => "mul_i64",
+        INSN_DIV_I64 => "div_i64",
+        INSN_ADD_WRAP_I64 => "add_wrap_i64",
+        INSN_SUB_WRAP_I64 => "sub_wrap_i64",
+        INSN_MUL_WRAP_I64 => "mul_wrap_i64",
+        INSN_DIV_WRAP_I64 => "div_wrap_i64",
+        INSN_ADD_SAT_I64 => "add_sat_i64",
+        INSN_SUB_SAT_I64 => "sub_sat_i64",
+        INSN_MUL_SAT_I64 => "mul_sat_i64",
+        INSN_DIV_SAT_I64 => "div_sat_i64",
+        INSN_ADD_F64 => "add_f64",
+        INSN_SUB_F64 => "sub_f64",
+        INSN_MUL_F64 => "mul_f64",
+        INSN_DIV_F64 => "div_f64",
+        INSN_ADD_STR => "add_str",
+        INSN_MOD_I64 => "mod_i64",
+        INSN_MOD_F64 => "mod_f64",
+        INSN_NEG_I64 => "neg_i64",
+        INSN_NEG_F64 => "neg_f64",
+        INSN_AND_I64 => "and_i64",
+        INSN_OR_I64 => "or_i64",
+        INSN_XOR_I64 => "xor_i64",
+        INSN_NOT_I64 => "not_i64",
+        INSN_SHL_I64 => "shl_i64",
+        INSN_SHR_I64 => "shr_i64",
+        INSN_AND_BOOL => "and_bool",
+        INSN_OR_BOOL => "or_bool",
+        INSN_NOT_BOOL => "not_bool",
+        INSN_EQ_I64 => "eq_i64",
+        INSN_LT_I64 => "lt_i64",
+        INSN_GT_I64 => "gt_i64",
+        INSN_LE_I64 => "le_i64",
+        INSN_GE_I64 => "ge_i64",
+        INSN_EQ_F64 => "eq_f64",
+        INSN_LT_F64 => "lt_f64",
+        INSN_GT_F64 => "gt_f64",
+        INSN_LE_F64 => "le_f64",
+        INSN_GE_F64 => "ge_f64",
+        INSN_EQ_STR => "eq_str",
+        INSN_LT_STR => "lt_str",
+        INSN_GT_STR => "gt_str",
+        INSN_J => "j",
+        INSN_JNZ => "jnz",
+        INSN_JZ => "jz",
+        INSN_CALL => "call",
+        INSN_RET => "ret",
+        INSN_LEN_STR => "len_str",
+        INSN_SUBSTR_STR => "substr_str",
+        INSN_FIND_STR => "find_str",
+        INSN_CHAR_AT_STR => "char_at_str",
+        INSN_SPLIT_FIRST_STR => "split_first_str",
+        INSN_TRIM_STR => "trim_str",
+        INSN_UPPER_STR => "upper_str",
+        INSN_LOWER_STR => "lower_str",
+        INSN_STARTS_WITH_STR => "starts_with_str",
+        INSN_NEW_ARRAY_I64 => "new_array_i64",
+        INSN_NEW_ARRAY_F64 => "new_array_f64",
+        INSN_NEW_ARRAY_STR => "new_array_str",
+        INSN_NEW_ARRAY_BOOL => "new_array_bool",
+        INSN_GET_I64 => "get_i64",
+        INSN_GET_F64 => "get_f64",
+        INSN_GET_STR => "get_str",
+        INSN_GET_BOOL => "get_bool",
+        INSN_SET_I64 => "set_i64",
+        INSN_SET_F64 => "set_f64",
+        INSN_SET_STR => "set_str",
+        INSN_SET_BOOL => "set_bool",
+        INSN_ARRAY_LEN_I64 => "array_len_i64",
+        INSN_ARRAY_LEN_F64 => "array_len_f64",
+        INSN_ARRAY_LEN_STR => "array_len_str",
+        INSN_ARRAY_LEN_BOOL => "array_len_bool",
+        INSN_APPEND_I64 => "append_i64",
+        INSN_APPEND_F64 => "append_f64",
+        INSN_APPEND_STR => "append_str",
+        INSN_APPEND_BOOL => "append_bool",
+        _ => return None,
+    })
+}
+
+/// Returns the offsets of all instructions in the code, including the end of the code
+pub fn instruction_starts(code: &[u8]) -> Vec<bool> {
+    let mut starts = vec![false; code.len() + 1];
+    let mut pc = 0;
+    while pc + 2 <= code.len() {
+        starts[pc] = true;
+        let insn = u16::from_le_bytes([code[pc], code[pc + 1]]);
+        pc += 2 + operand_size(insn);
+    }
+    if pc == code.len() {
+        starts[pc] = true;
+    }
+    starts
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Type {
@@ -111,6 +452,8 @@ pub enum Type {
     Float,
     String,
     CodeAddress,
+    Bool,
+    Array(&'static Type),
 }
 
 impl Type {
@@ -125,4 +468,64 @@ impl Type {
     pub fn is_string(&self) -> bool {
         *self == Type::String
     }
+
+    pub fn is_bool(&self) -> bool {
+        *self == Type::Bool
+    }
+
+    pub fn is_code_address(&self) -> bool {
+        *self == Type::CodeAddress
+    }
+
+    /// Returns the type of arrays with elements of the type, `None` if there are no such arrays
+    pub fn array(element: Type) -> Option<Self> {
+        Some(Type::Array(match element {
+            Type::Int => &Type::Int,
+            Type::Float => &Type::Float,
+            Type::String => &Type::String,
+            Type::Bool => &Type::Bool,
+            _ => return None,
+        }))
+    }
+
+    pub fn tag(&self) -> u8 {
+        match self {
+            Type::Int => TYPE_I64,
+            Type::Float => TYPE_F64,
+            Type::String => TYPE_STR,
+            Type::CodeAddress => TYPE_ADDR,
+            Type::Bool => TYPE_BOOL,
+            Type::Array(element) => TYPE_ARRAY | element.tag(),
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            TYPE_I64 => Some(Type::Int),
+            TYPE_F64 => Some(Type::Float),
+            TYPE_STR => Some(Type::String),
+            TYPE_ADDR => Some(Type::CodeAddress),
+            TYPE_BOOL => Some(Type::Bool),
+            tag if tag & 0xF0 == TYPE_ARRAY => Type::array(Type::from_tag(tag & 0x0F)?),
+            _ => None,
+        }
+    }
+}
+
+/// Stack effect of a native word, the types are listed from the bottom to the top of the stack
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub inputs: Vec<Type>,
+    pub outputs: Vec<Type>,
+}
+
+impl Signature {
+    /// Whether natives can pop the inputs and push the outputs, which excludes arrays and code
+    /// addresses
+    pub fn is_supported(&self) -> bool {
+        self.inputs
+            .iter()
+            .chain(&self.outputs)
+            .all(|type_| matches!(type_, Type::Int | Type::Float | Type::String | Type::Bool))
+    }
 }
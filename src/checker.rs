@@ -1,318 +1,1116 @@
-use std::io::{Cursor, Error, ErrorKind, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Display, Formatter},
+    io::{Cursor, Error, Result},
+};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::bytecode::*;
+use crate::{bytecode::*, debug::DebugInfo};
 
-pub fn check(bytes: &[u8]) -> Result<(usize, usize)> {
-    if bytes.len() % 2 != 0 {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Instructions not aligned correctly",
-        ));
+/// Maximum number of rounds to settle the stack effects of recursive subroutines
+const MAX_EFFECT_ROUNDS: usize = 16;
+/// Maximum depth of `pick` and `roll`
+const MAX_DEPTH: i64 = 1024;
+
+#[derive(Clone, Copy)]
+struct Instruction {
+    pos: u64,
+    insn: u16,
+    operand: i64,
+}
+
+/// An entry on the compile-time type stack
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Known(Type),
+    /// The n-th entry below the stack of the caller, counted from the top
+    Param(usize),
+}
+
+impl Debug for Slot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Slot::Known(type_) => write!(f, "{type_:?}"),
+            Slot::Param(i) => write!(f, "${i}"),
+        }
     }
-    let mut read = Cursor::new(bytes);
-    let mut max_stack_size = 0;
-    let mut stack: Vec<Type> = Vec::new();
-    while bytes.len() - read.position() as usize >= 2 {
-        let insn = read.read_u16::<LittleEndian>()?;
-        match insn {
-            INSN_DROP => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("pop", read.position() - 2),
-                    ));
-                }
-                stack.pop().unwrap();
-            }
-            INSN_LOAD => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("load", read.position() - 2),
-                    ));
-                }
-                let x = stack.pop().unwrap();
-                if !x.is_int() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("load", read.position() - 2),
-                    ));
-                }
-                stack.push(Type::String);
-            }
-            INSN_SWAP => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("swap", read.position() - 2),
-                    ));
-                }
-                let x = stack.pop().unwrap();
-                let y = stack.pop().unwrap();
-                stack.push(x);
-                stack.push(y);
-            }
-            INSN_TROT => {
-                if stack.len() < 3 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("trot", read.position() - 2),
-                    ));
-                }
-                let x = stack.pop().unwrap();
-                let y = stack.pop().unwrap();
-                let z = stack.pop().unwrap();
-                stack.push(y);
-                stack.push(x);
-                stack.push(z);
-            }
-            INSN_DUP => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("dup", read.position() - 2),
-                    ));
-                }
-                stack.push(stack[stack.len() - 1]);
-            }
-            INSN_DDUP => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("ddup", read.position() - 2),
-                    ));
-                }
-                stack.push(stack[stack.len() - 2]);
-            }
-            INSN_TDUP => {
-                if stack.len() < 3 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("tdup", read.position() - 2),
-                    ));
-                }
-                stack.push(stack[stack.len() - 3]);
-            }
-            INSN_J => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("j", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "j", read.position() - 2)?;
-            }
-            INSN_JNZ => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("jnz", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "jnz", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Int, "jnz", read.position() - 2)?;
-            }
-            INSN_JZ => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("jz", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "jz", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Int, "jz", read.position() - 2)?;
-            }
-            INSN_CALL => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("call", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "call", read.position() - 2)?;
-            }
-            INSN_PUSH_I64 => {
-                stack.push(Type::Int);
-                read.read_i64::<LittleEndian>()?;
-            }
-            INSN_PUSH_F64 => {
-                stack.push(Type::Float);
-                read.read_f64::<LittleEndian>()?;
-            }
-            INSN_NUMCONV_I64 => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("numconv-int", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Float, "numconv-int", read.position() - 2)?;
-                stack.push(Type::Int);
-            }
-            INSN_NUMCONV_F64 => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("numconv-float", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "numconv-float", read.position() - 2)?;
-                stack.push(Type::Float);
+}
+
+/// The type stack at a point in the code, relative to the stack at the entry of the subroutine
+#[derive(Clone, PartialEq)]
+struct State {
+    /// Amount of entries taken from the stack of the caller
+    depth: usize,
+    slots: Vec<Slot>,
+    /// Locals of the subroutine, `None` if a local is not initialized on every path
+    locals: Vec<Option<Slot>>,
+}
+
+/// Types the entries taken from the stack of the caller are required to have
+struct Params {
+    allowed: bool,
+    types: Vec<Option<Type>>,
+    /// Entries required to have the same type as another entry point towards it, the type of
+    /// such a group is stored at the entry it ends in
+    parents: Vec<usize>,
+}
+
+impl Params {
+    fn find(&self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn type_of(&self, i: usize) -> Option<Type> {
+        self.types[self.find(i)]
+    }
+
+    /// Requires both entries to have the same type, returns the entry representing both
+    fn join(&mut self, i: usize, j: usize) -> Option<usize> {
+        let (i, j) = (self.find(i), self.find(j));
+        let (i, j) = (i.min(j), i.max(j));
+        let type_ = match (self.types[i], self.types[j]) {
+            (Some(x), Some(y)) if x != y => return None,
+            (x, y) => x.or(y),
+        };
+        self.parents[j] = i;
+        self.types[i] = type_;
+        Some(i)
+    }
+}
+
+#[derive(Clone)]
+struct Effect {
+    inputs: Vec<Option<Type>>,
+    /// Pairs of inputs that are required to have the same, not yet known type
+    equal: Vec<(usize, usize)>,
+    outputs: Vec<Slot>,
+    max_stack_size: usize,
+}
+
+impl Effect {
+    /// Compares the signatures of the effects, the stack size of recursive subroutines is unbounded
+    fn same(x: &Option<Effect>, y: &Option<Effect>) -> bool {
+        match (x, y) {
+            (Some(x), Some(y)) => {
+                x.inputs == y.inputs && x.equal == y.equal && x.outputs == y.outputs
             }
-            INSN_ABORT => {}
-            INSN_EXIT => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("exit", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "exit", read.position() - 2)?;
+            (x, y) => x.is_none() && y.is_none(),
+        }
+    }
+}
+
+struct Summary {
+    max_stack_size: usize,
+    end: Option<State>,
+    effect: Option<Effect>,
+}
+
+struct Checker<'a> {
+    len: u64,
+    debug_info: Option<&'a DebugInfo>,
+    imports: &'a [(String, Signature)],
+    globals: &'a [Type],
+    instructions: Vec<Instruction>,
+    indices: HashMap<u64, usize>,
+    targets: HashSet<usize>,
+    /// Stack effects of all subroutines, `None` if a subroutine never returns
+    effects: HashMap<usize, Option<Effect>>,
+    in_progress: HashSet<usize>,
+    recursive: bool,
+}
+
+/// Verifies the bytecode by propagating type stacks along every edge of its control-flow graph.
+///
+/// Jump targets have to be pushed by a `PUSH_ADDR` directly in front of the jump. Every called
+/// subroutine is checked once and its stack effect is applied at all of its call sites. Returns
+/// the maximum stack size of all paths, which is unbounded for recursive programs, and the stack
+/// size at the end of the code. Native words are checked against the signatures of the imports
+/// and globals against their types in the data segment. Errors point to the source if debug
+/// information is given.
+pub fn check(
+    bytes: &[u8],
+    imports: &[(String, Signature)],
+    globals: &[Type],
+    debug_info: Option<&DebugInfo>,
+) -> Result<(Option<usize>, usize)> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(Error::other("Instructions not aligned correctly"));
+    }
+    let instructions = decode(bytes)?;
+    let mut indices = HashMap::with_capacity(instructions.len() + 1);
+    for (i, instruction) in instructions.iter().enumerate() {
+        indices.insert(instruction.pos, i);
+    }
+    indices.insert(bytes.len() as u64, instructions.len());
+    let mut targets = HashSet::new();
+    for i in 1..instructions.len() {
+        if is_jump(instructions[i].insn) && instructions[i - 1].insn == INSN_PUSH_ADDR {
+            let pos = Position {
+                offset: instructions[i].pos,
+                debug_info,
+            };
+            let target = instructions[i - 1].operand;
+            if target < 0 || target as usize > bytes.len() {
+                return Err(Error::other(format!(
+                    "Jump target 0x{target:08X} out of bounds at {pos}"
+                )));
             }
-            INSN_PANIC => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("panic", read.position() - 2),
-                    ));
+            let Some(target) = indices.get(&(target as u64)) else {
+                return Err(Error::other(format!(
+                    "Jump into the middle of an instruction at 0x{target:08X} at {pos}"
+                )));
+            };
+            targets.insert(*target);
+        }
+    }
+    let mut checker = Checker {
+        len: bytes.len() as u64,
+        debug_info,
+        imports,
+        globals,
+        instructions,
+        indices,
+        targets,
+        effects: HashMap::new(),
+        in_progress: HashSet::new(),
+        recursive: false,
+    };
+    let mut main = checker.analyze(0, false)?;
+    if checker.recursive {
+        // Recursive call sites were skipped while the effect of their callee was still unknown
+        let mut round = 0;
+        loop {
+            let mut changed = false;
+            let mut entries: Vec<_> = checker.effects.keys().copied().collect();
+            entries.sort_unstable();
+            for entry in entries {
+                let effect = checker.analyze(entry, true)?.effect;
+                if !Effect::same(&checker.effects[&entry], &effect) {
+                    checker.effects.insert(entry, effect);
+                    changed = true;
                 }
-                expect_type_on_stack(&mut stack, Type::String, "panic", read.position() - 2)?;
-            }
-            INSN_PRINTLN => {}
-            INSN_INPUT => {
-                stack.push(Type::String);
             }
-            INSN_GC => {}
-            INSN_PRINT_I64 => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("print-int", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Int, "print-int", read.position() - 2)?;
+            if !changed {
+                break;
             }
-            INSN_PRINT_F64 => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("print-float", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::Float, "print-float", read.position() - 2)?;
+            round += 1;
+            if round == MAX_EFFECT_ROUNDS {
+                return Err(Error::other(
+                    "Could not determine stack effects of recursive subroutines",
+                ));
             }
-            INSN_PRINT_STR => {
-                if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("print-string", read.position() - 2),
-                    ));
+        }
+        main = checker.analyze(0, false)?;
+    }
+    Ok((
+        if checker.recursive {
+            None
+        } else {
+            Some(main.max_stack_size)
+        },
+        main.end.map_or(0, |state| state.slots.len()),
+    ))
+}
+
+/// Offset of an instruction, displayed as source location if debug information is available
+#[derive(Clone, Copy)]
+struct Position<'a> {
+    offset: u64,
+    debug_info: Option<&'a DebugInfo>,
+}
+
+impl Display for Position<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self
+            .debug_info
+            .and_then(|debug_info| debug_info.location(self.offset))
+        {
+            Some(location) => write!(f, "{location}"),
+            None => write!(f, "position 0x{:08X}", self.offset),
+        }
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn pos(&self, index: usize) -> Position<'a> {
+        self.position(self.instructions.get(index).map_or(self.len, |it| it.pos))
+    }
+
+    fn position(&self, offset: u64) -> Position<'a> {
+        Position {
+            offset,
+            debug_info: self.debug_info,
+        }
+    }
+
+    /// Returns the stack effect of the subroutine, `None` if it is still being analyzed
+    fn effect(&mut self, entry: usize) -> Result<Option<Option<Effect>>> {
+        if let Some(effect) = self.effects.get(&entry) {
+            return Ok(Some(effect.clone()));
+        }
+        if self.in_progress.contains(&entry) {
+            self.recursive = true;
+            return Ok(None);
+        }
+        self.in_progress.insert(entry);
+        let effect = self.analyze(entry, true)?.effect;
+        self.in_progress.remove(&entry);
+        self.effects.insert(entry, effect.clone());
+        Ok(Some(effect))
+    }
+
+    fn analyze(&mut self, entry: usize, subroutine: bool) -> Result<Summary> {
+        let end = self.instructions.len();
+        let ret = end + 1;
+        let mut states: Vec<Option<State>> = vec![None; end + 2];
+        let mut params = Params {
+            allowed: subroutine,
+            types: Vec::new(),
+            parents: Vec::new(),
+        };
+        let mut worklist = Vec::new();
+        let mut max_stack_size = 0;
+        states[entry] = Some(State {
+            depth: 0,
+            slots: Vec::new(),
+            locals: Vec::new(),
+        });
+        if entry != end {
+            worklist.push(entry);
+        }
+        while let Some(i) = worklist.pop() {
+            let instruction = self.instructions[i];
+            let mut state = states[i].clone().unwrap();
+            let pos = self.position(instruction.pos);
+            step(&instruction, &mut state, &mut params, pos)?;
+            max_stack_size = max_stack_size.max(state.height());
+            let mut successors = Vec::with_capacity(2);
+            match instruction.insn {
+                INSN_J => successors.push(self.jump_target(i)?),
+                INSN_JNZ | INSN_JZ => {
+                    successors.push(self.jump_target(i)?);
+                    successors.push(i + 1);
                 }
-                expect_type_on_stack(
-                    &mut stack,
-                    Type::String,
-                    "print-string",
-                    read.position() - 2,
-                )?;
-            }
-            INSN_ADD_I64 | INSN_SUB_I64 | INSN_MUL_I64 | INSN_DIV_I64 => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("math-int", read.position() - 2),
-                    ));
+                INSN_CALL => {
+                    let target = self.jump_target(i)?;
+                    // Unknown effects belong to recursive calls, the fall-through is visited again later
+                    if let Some(Some(effect)) = self.effect(target)? {
+                        max_stack_size = max_stack_size.max(state.height() + effect.max_stack_size);
+                        apply_effect(&effect, &mut state, &mut params, "call", pos)?;
+                        max_stack_size = max_stack_size.max(state.height());
+                        successors.push(i + 1);
+                    }
                 }
-                expect_type_on_stack(&mut stack, Type::Int, "math-int", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Int, "math-int", read.position() - 2)?;
-                stack.push(Type::Int);
-            }
-            INSN_ADD_F64 | INSN_SUB_F64 | INSN_MUL_F64 | INSN_DIV_F64 => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("math-float", read.position() - 2),
-                    ));
+                INSN_NATIVE => {
+                    let Some((_, signature)) = usize::try_from(instruction.operand)
+                        .ok()
+                        .and_then(|index| self.imports.get(index))
+                    else {
+                        return Err(Error::other(format!(
+                            "Unknown import {} at {pos}",
+                            instruction.operand
+                        )));
+                    };
+                    let effect = Effect {
+                        inputs: signature.inputs.iter().rev().copied().map(Some).collect(),
+                        equal: Vec::new(),
+                        outputs: signature.outputs.iter().copied().map(Slot::Known).collect(),
+                        max_stack_size: 0,
+                    };
+                    apply_effect(&effect, &mut state, &mut params, "native", pos)?;
+                    max_stack_size = max_stack_size.max(state.height());
+                    successors.push(i + 1);
                 }
-                expect_type_on_stack(&mut stack, Type::Float, "math-float", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Float, "math-float", read.position() - 2)?;
-                stack.push(Type::Float);
-            }
-            INSN_ADD_STR => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("add-string", read.position() - 2),
-                    ));
+                INSN_GLOAD | INSN_GSTORE => {
+                    let Some(&type_) = usize::try_from(instruction.operand)
+                        .ok()
+                        .and_then(|index| self.globals.get(index))
+                    else {
+                        return Err(Error::other(format!(
+                            "Unknown global {} at {pos}",
+                            instruction.operand
+                        )));
+                    };
+                    if instruction.insn == INSN_GLOAD {
+                        state.push(type_);
+                        max_stack_size = max_stack_size.max(state.height());
+                    } else {
+                        state.require(&mut params, 1, "gstore", pos)?;
+                        expect_type_on_stack(&mut state, &mut params, type_, "gstore", pos)?;
+                    }
+                    successors.push(i + 1);
                 }
-                expect_type_on_stack(&mut stack, Type::String, "add-string", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::String, "add-string", read.position() - 2)?;
-                stack.push(Type::String);
-            }
-            INSN_EQ_I64 | INSN_LT_I64 | INSN_GT_I64 | INSN_LE_I64 | INSN_GE_I64 => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("comp-int", read.position() - 2),
-                    ));
+                INSN_RET => {
+                    if !subroutine {
+                        return Err(Error::other(format!(
+                            "Return outside of subroutine at {pos}"
+                        )));
+                    }
+                    successors.push(ret);
                 }
-                expect_type_on_stack(&mut stack, Type::Int, "comp-int", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Int, "comp-int", read.position() - 2)?;
-                stack.push(Type::Int);
+                INSN_ABORT | INSN_EXIT | INSN_PANIC => {}
+                _ => successors.push(i + 1),
             }
-            INSN_EQ_F64 | INSN_LT_F64 | INSN_GT_F64 | INSN_LE_F64 | INSN_GE_F64 => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("comp-float", read.position() - 2),
-                    ));
+            for successor in successors {
+                let pos = if successor == ret {
+                    pos
+                } else {
+                    self.pos(successor)
+                };
+                let changed = match &mut states[successor] {
+                    None => {
+                        states[successor] = Some(state.clone());
+                        true
+                    }
+                    Some(existing) => merge(existing, &state, &mut params, pos)?,
+                };
+                if changed && successor < end {
+                    worklist.push(successor);
                 }
-                expect_type_on_stack(&mut stack, Type::Float, "comp-float", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::Float, "comp-float", read.position() - 2)?;
-                stack.push(Type::Int);
             }
-            INSN_EQ_STR => {
-                if stack.len() < 2 {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        invalid_stack("comp-string", read.position() - 2),
-                    ));
-                }
-                expect_type_on_stack(&mut stack, Type::String, "comp-string", read.position() - 2)?;
-                expect_type_on_stack(&mut stack, Type::String, "comp-string", read.position() - 2)?;
-                stack.push(Type::Int);
+        }
+        let effect = states[ret].take().map(|state| Effect {
+            inputs: (0..state.depth).map(|i| params.type_of(i)).collect(),
+            equal: (0..state.depth)
+                .map(|i| (i, params.find(i)))
+                .filter(|&(i, j)| i != j)
+                .collect(),
+            outputs: state.slots,
+            max_stack_size,
+        });
+        Ok(Summary {
+            max_stack_size,
+            end: states[end].take(),
+            effect,
+        })
+    }
+
+    fn jump_target(&self, i: usize) -> Result<usize> {
+        let pos = self.pos(i);
+        if i == 0 || self.instructions[i - 1].insn != INSN_PUSH_ADDR || self.targets.contains(&i) {
+            return Err(Error::other(format!(
+                "Could not determine target of jump instruction at {pos}"
+            )));
+        }
+        Ok(self.indices[&(self.instructions[i - 1].operand as u64)])
+    }
+}
+
+impl State {
+    /// Height of the stack relative to the entry of the subroutine
+    fn height(&self) -> usize {
+        self.slots.len().saturating_sub(self.depth)
+    }
+
+    fn require(
+        &mut self,
+        params: &mut Params,
+        len: usize,
+        insn_type: &str,
+        pos: Position,
+    ) -> Result<()> {
+        if self.slots.len() >= len {
+            return Ok(());
+        }
+        if !params.allowed {
+            return Err(Error::other(invalid_stack(insn_type, pos)));
+        }
+        self.take_params(params, len - self.slots.len());
+        Ok(())
+    }
+
+    fn take_params(&mut self, params: &mut Params, amount: usize) {
+        for _ in 0..amount {
+            self.slots.insert(0, Slot::Param(self.depth));
+            self.depth += 1;
+        }
+        if params.types.len() < self.depth {
+            params.types.resize(self.depth, None);
+            params.parents.extend(params.parents.len()..self.depth);
+        }
+    }
+
+    fn push(&mut self, type_: Type) {
+        self.slots.push(Slot::Known(type_));
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<Instruction>> {
+    let mut read = Cursor::new(bytes);
+    let mut instructions = Vec::new();
+    while bytes.len() - read.position() as usize >= 2 {
+        let pos = read.position();
+        let insn = read.read_u16::<LittleEndian>()?;
+        let operand = match operand_size(insn) {
+            8 => read.read_i64::<LittleEndian>().map_err(|_| {
+                Error::other(format!(
+                    "Missing operand of instruction at position 0x{pos:08X}"
+                ))
+            })?,
+            _ => 0,
+        };
+        instructions.push(Instruction { pos, insn, operand });
+    }
+    Ok(instructions)
+}
+
+fn is_jump(insn: u16) -> bool {
+    matches!(insn, INSN_J | INSN_JNZ | INSN_JZ | INSN_CALL)
+}
+
+/// Merges an incoming state into the state of a join point, returns whether the state changed
+fn merge(
+    existing: &mut State,
+    incoming: &State,
+    params: &mut Params,
+    pos: Position,
+) -> Result<bool> {
+    let mut incoming = incoming.clone();
+    let before = existing.clone();
+    if existing.depth < incoming.depth {
+        existing.take_params(params, incoming.depth - existing.depth);
+    } else if incoming.depth < existing.depth {
+        incoming.take_params(params, existing.depth - incoming.depth);
+    }
+    // Both states start with the same entries from the caller, compare them from there on
+    let aligned = existing.slots.clone();
+    let mismatch = || {
+        Error::other(format!(
+            "Stack mismatch at join point at {pos}: expected {aligned:?} but found {:?}",
+            incoming.slots
+        ))
+    };
+    if existing.slots.len() != incoming.slots.len() {
+        return Err(mismatch());
+    }
+    for (x, y) in existing.slots.iter_mut().zip(&incoming.slots) {
+        if *x != *y {
+            *x = unify(*x, *y, params).ok_or_else(mismatch)?;
+        }
+    }
+    // Locals stay initialized only if they hold the same type on both paths
+    existing.locals.truncate(incoming.locals.len());
+    for (x, y) in existing.locals.iter_mut().zip(&incoming.locals) {
+        *x = match (*x, *y) {
+            (Some(x), Some(y)) if x == y => Some(x),
+            (Some(x), Some(y)) => unify(x, y, params),
+            _ => None,
+        };
+    }
+    Ok(*existing != before)
+}
+
+/// Returns the slot both slots can be replaced with, constraining the entries taken from the caller
+fn unify(x: Slot, y: Slot, params: &mut Params) -> Option<Slot> {
+    match (x, y) {
+        (Slot::Known(x), Slot::Known(y)) => (x == y).then_some(Slot::Known(x)),
+        (Slot::Known(type_), Slot::Param(i)) | (Slot::Param(i), Slot::Known(type_)) => {
+            constrain(params, i, type_).map(Slot::Known)
+        }
+        (Slot::Param(i), Slot::Param(j)) => {
+            let i = params.join(i, j)?;
+            Some(params.types[i].map_or(Slot::Param(i), Slot::Known))
+        }
+    }
+}
+
+/// Requires the entry taken from the stack of the caller to have the given type
+fn constrain(params: &mut Params, i: usize, type_: Type) -> Option<Type> {
+    let i = params.find(i);
+    match params.types[i] {
+        Some(x) if x != type_ => None,
+        _ => {
+            params.types[i] = Some(type_);
+            Some(type_)
+        }
+    }
+}
+
+fn apply_effect(
+    effect: &Effect,
+    state: &mut State,
+    params: &mut Params,
+    insn_type: &str,
+    pos: Position,
+) -> Result<()> {
+    state.require(params, effect.inputs.len(), insn_type, pos)?;
+    let args = state
+        .slots
+        .split_off(state.slots.len() - effect.inputs.len());
+    let arg = |i: usize| args[args.len() - 1 - i];
+    for (i, input) in effect.inputs.iter().enumerate() {
+        if let Some(type_) = input {
+            expect_type(arg(i), params, *type_, insn_type, pos)?;
+        }
+    }
+    for &(i, j) in &effect.equal {
+        if unify(arg(i), arg(j), params).is_none() {
+            return Err(Error::other(format!(
+                "Invalid type on stack in {insn_type} instruction at {pos}: expected {:?} and {:?} to have the same type",
+                arg(i), arg(j)
+            )));
+        }
+    }
+    for output in &effect.outputs {
+        state.slots.push(match output {
+            Slot::Known(type_) => Slot::Known(*type_),
+            Slot::Param(i) => arg(*i),
+        });
+    }
+    Ok(())
+}
+
+fn step(
+    instruction: &Instruction,
+    state: &mut State,
+    params: &mut Params,
+    pos: Position,
+) -> Result<()> {
+    match instruction.insn {
+        INSN_DROP => {
+            state.require(params, 1, "pop", pos)?;
+            state.slots.pop().unwrap();
+        }
+        INSN_LOAD => {
+            state.require(params, 1, "load", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "load", pos)?;
+            state.push(Type::String);
+        }
+        INSN_SWAP => {
+            state.require(params, 2, "swap", pos)?;
+            let x = state.slots.pop().unwrap();
+            let y = state.slots.pop().unwrap();
+            state.slots.push(x);
+            state.slots.push(y);
+        }
+        INSN_TROT => {
+            state.require(params, 3, "trot", pos)?;
+            let x = state.slots.pop().unwrap();
+            let y = state.slots.pop().unwrap();
+            let z = state.slots.pop().unwrap();
+            state.slots.push(y);
+            state.slots.push(x);
+            state.slots.push(z);
+        }
+        INSN_DUP => {
+            state.require(params, 1, "dup", pos)?;
+            state.slots.push(state.slots[state.slots.len() - 1]);
+        }
+        INSN_DDUP => {
+            state.require(params, 2, "ddup", pos)?;
+            state.slots.push(state.slots[state.slots.len() - 2]);
+        }
+        INSN_TDUP => {
+            state.require(params, 3, "tdup", pos)?;
+            state.slots.push(state.slots[state.slots.len() - 3]);
+        }
+        INSN_OVER => {
+            state.require(params, 2, "over", pos)?;
+            state.slots.push(state.slots[state.slots.len() - 2]);
+        }
+        INSN_NIP => {
+            state.require(params, 2, "nip", pos)?;
+            state.slots.remove(state.slots.len() - 2);
+        }
+        INSN_TUCK => {
+            state.require(params, 2, "tuck", pos)?;
+            let x = state.slots[state.slots.len() - 1];
+            state.slots.insert(state.slots.len() - 2, x);
+        }
+        INSN_DUP2 => {
+            state.require(params, 2, "dup2", pos)?;
+            state.slots.extend_from_within(state.slots.len() - 2..);
+        }
+        INSN_DROP2 => {
+            state.require(params, 2, "drop2", pos)?;
+            state.slots.truncate(state.slots.len() - 2);
+        }
+        INSN_SWAP2 => {
+            state.require(params, 4, "swap2", pos)?;
+            let len = state.slots.len();
+            state.slots[len - 4..].rotate_left(2);
+        }
+        INSN_PICK | INSN_ROLL => {
+            let depth = instruction.operand;
+            if !(0..MAX_DEPTH).contains(&depth) {
+                return Err(Error::other(format!("Invalid depth {depth} at {pos}")));
             }
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Invalid instruction 0x{insn:04X}"),
-                ));
+            state.require(params, depth as usize + 1, "pick-roll", pos)?;
+            let index = state.slots.len() - 1 - depth as usize;
+            let x = if instruction.insn == INSN_PICK {
+                state.slots[index]
+            } else {
+                state.slots.remove(index)
+            };
+            state.slots.push(x);
+        }
+        INSN_LOAD_LOCAL => {
+            let index = local_index(instruction.operand, pos)?;
+            let Some(Some(x)) = state.locals.get(index).copied() else {
+                return Err(Error::other(format!(
+                    "Local {index} used before initialization at {pos}"
+                )));
+            };
+            state.slots.push(x);
+        }
+        INSN_STORE_LOCAL => {
+            let index = local_index(instruction.operand, pos)?;
+            state.require(params, 1, "store-local", pos)?;
+            let x = state.slots.pop().unwrap();
+            if state.locals.len() <= index {
+                state.locals.resize(index + 1, None);
             }
+            state.locals[index] = Some(x);
+        }
+        INSN_J => {
+            state.require(params, 1, "j", pos)?;
+            expect_type_on_stack(state, params, Type::CodeAddress, "j", pos)?;
+        }
+        INSN_JNZ => {
+            state.require(params, 2, "jnz", pos)?;
+            expect_type_on_stack(state, params, Type::CodeAddress, "jnz", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "jnz", pos)?;
+        }
+        INSN_JZ => {
+            state.require(params, 2, "jz", pos)?;
+            expect_type_on_stack(state, params, Type::CodeAddress, "jz", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "jz", pos)?;
+        }
+        INSN_CALL => {
+            state.require(params, 1, "call", pos)?;
+            expect_type_on_stack(state, params, Type::CodeAddress, "call", pos)?;
+        }
+        // Handled by the checker, which knows the signatures of the imports and the globals
+        INSN_RET | INSN_NATIVE | INSN_GLOAD | INSN_GSTORE => {}
+        INSN_PUSH_I64 => {
+            state.push(Type::Int);
+        }
+        INSN_PUSH_F64 => {
+            state.push(Type::Float);
+        }
+        INSN_PUSH_BOOL => {
+            state.push(Type::Bool);
+        }
+        INSN_PUSH_ADDR => {
+            state.push(Type::CodeAddress);
+        }
+        INSN_NUMCONV_I64 => {
+            state.require(params, 1, "numconv-int", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "numconv-int", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_NUMCONV_F64 => {
+            state.require(params, 1, "numconv-float", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "numconv-float", pos)?;
+            state.push(Type::Float);
+        }
+        INSN_NUMCONV_BOOL => {
+            state.require(params, 1, "numconv-bool", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "numconv-bool", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_NUMCONV_ADDR => {
+            state.require(params, 1, "numconv-addr", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "numconv-addr", pos)?;
+            state.push(Type::CodeAddress);
+        }
+        INSN_NUMCONV_STR => {
+            state.require(params, 1, "numconv-string", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "numconv-string", pos)?;
+            state.push(Type::String);
+        }
+        INSN_FLOATCONV_STR => {
+            state.require(params, 1, "floatconv-string", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "floatconv-string", pos)?;
+            state.push(Type::String);
+        }
+        INSN_STRCONV_I64 => {
+            state.require(params, 1, "strconv-int", pos)?;
+            expect_type_on_stack(state, params, Type::String, "strconv-int", pos)?;
+            state.push(Type::Int);
+            state.push(Type::Bool);
+        }
+        INSN_STRCONV_F64 => {
+            state.require(params, 1, "strconv-float", pos)?;
+            expect_type_on_stack(state, params, Type::String, "strconv-float", pos)?;
+            state.push(Type::Float);
+            state.push(Type::Bool);
+        }
+        INSN_ADDRCONV_I64 => {
+            state.require(params, 1, "addrconv-int", pos)?;
+            expect_type_on_stack(state, params, Type::CodeAddress, "addrconv-int", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_BOOLCONV_I64 => {
+            state.require(params, 1, "boolconv-int", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "boolconv-int", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_ABORT => {}
+        INSN_EXIT => {
+            state.require(params, 1, "exit", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "exit", pos)?;
         }
-        if stack.len() > max_stack_size {
-            max_stack_size = stack.len();
+        INSN_PANIC => {
+            state.require(params, 1, "panic", pos)?;
+            expect_type_on_stack(state, params, Type::String, "panic", pos)?;
+        }
+        INSN_PRINTLN => {}
+        INSN_INPUT => {
+            state.push(Type::String);
+        }
+        INSN_GC => {}
+        INSN_PRINT_I64 => {
+            state.require(params, 1, "print-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "print-int", pos)?;
+        }
+        INSN_PRINT_F64 => {
+            state.require(params, 1, "print-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "print-float", pos)?;
+        }
+        INSN_PRINT_STR => {
+            state.require(params, 1, "print-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "print-string", pos)?;
+        }
+        INSN_PRINT_BOOL => {
+            state.require(params, 1, "print-bool", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "print-bool", pos)?;
+        }
+        INSN_ADD_I64 | INSN_SUB_I64 | INSN_MUL_I64 | INSN_DIV_I64 | INSN_ADD_WRAP_I64
+        | INSN_SUB_WRAP_I64 | INSN_MUL_WRAP_I64 | INSN_DIV_WRAP_I64 | INSN_ADD_SAT_I64
+        | INSN_SUB_SAT_I64 | INSN_MUL_SAT_I64 | INSN_DIV_SAT_I64 | INSN_MOD_I64 | INSN_AND_I64
+        | INSN_OR_I64 | INSN_XOR_I64 | INSN_SHL_I64 | INSN_SHR_I64 => {
+            state.require(params, 2, "math-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "math-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "math-int", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_NEG_I64 | INSN_NOT_I64 => {
+            state.require(params, 1, "unary-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "unary-int", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_ADD_F64 | INSN_SUB_F64 | INSN_MUL_F64 | INSN_DIV_F64 | INSN_MOD_F64 => {
+            state.require(params, 2, "math-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "math-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "math-float", pos)?;
+            state.push(Type::Float);
+        }
+        INSN_NEG_F64 => {
+            state.require(params, 1, "neg-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "neg-float", pos)?;
+            state.push(Type::Float);
+        }
+        INSN_ADD_STR => {
+            state.require(params, 2, "add-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "add-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "add-string", pos)?;
+            state.push(Type::String);
+        }
+        INSN_AND_BOOL | INSN_OR_BOOL => {
+            state.require(params, 2, "logic", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "logic", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "logic", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_NOT_BOOL => {
+            state.require(params, 1, "not-bool", pos)?;
+            expect_type_on_stack(state, params, Type::Bool, "not-bool", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_EQ_I64 | INSN_LT_I64 | INSN_GT_I64 | INSN_LE_I64 | INSN_GE_I64 => {
+            state.require(params, 2, "comp-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "comp-int", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "comp-int", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_EQ_F64 | INSN_LT_F64 | INSN_GT_F64 | INSN_LE_F64 | INSN_GE_F64 => {
+            state.require(params, 2, "comp-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "comp-float", pos)?;
+            expect_type_on_stack(state, params, Type::Float, "comp-float", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_EQ_STR | INSN_LT_STR | INSN_GT_STR => {
+            state.require(params, 2, "comp-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "comp-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "comp-string", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_LEN_STR => {
+            state.require(params, 1, "len", pos)?;
+            expect_type_on_stack(state, params, Type::String, "len", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_SUBSTR_STR => {
+            state.require(params, 3, "substr", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "substr", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "substr", pos)?;
+            expect_type_on_stack(state, params, Type::String, "substr", pos)?;
+            state.push(Type::String);
+        }
+        INSN_CHAR_AT_STR => {
+            state.require(params, 2, "char-at", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "char-at", pos)?;
+            expect_type_on_stack(state, params, Type::String, "char-at", pos)?;
+            state.push(Type::String);
+        }
+        INSN_FIND_STR => {
+            state.require(params, 2, "find", pos)?;
+            expect_type_on_stack(state, params, Type::String, "find", pos)?;
+            expect_type_on_stack(state, params, Type::String, "find", pos)?;
+            state.push(Type::Int);
+        }
+        INSN_STARTS_WITH_STR => {
+            state.require(params, 2, "starts-with", pos)?;
+            expect_type_on_stack(state, params, Type::String, "starts-with", pos)?;
+            expect_type_on_stack(state, params, Type::String, "starts-with", pos)?;
+            state.push(Type::Bool);
+        }
+        INSN_SPLIT_FIRST_STR => {
+            state.require(params, 2, "split-first", pos)?;
+            expect_type_on_stack(state, params, Type::String, "split-first", pos)?;
+            expect_type_on_stack(state, params, Type::String, "split-first", pos)?;
+            state.push(Type::String);
+            state.push(Type::String);
+            state.push(Type::Bool);
+        }
+        INSN_TRIM_STR | INSN_UPPER_STR | INSN_LOWER_STR => {
+            state.require(params, 1, "unary-string", pos)?;
+            expect_type_on_stack(state, params, Type::String, "unary-string", pos)?;
+            state.push(Type::String);
+        }
+        INSN_NEW_ARRAY_I64 | INSN_NEW_ARRAY_F64 | INSN_NEW_ARRAY_STR | INSN_NEW_ARRAY_BOOL => {
+            state.push(array_type(instruction.insn));
+        }
+        INSN_GET_I64 | INSN_GET_F64 | INSN_GET_STR | INSN_GET_BOOL => {
+            state.require(params, 2, "get", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "get", pos)?;
+            expect_type_on_stack(state, params, array_type(instruction.insn), "get", pos)?;
+            state.push(element_type(instruction.insn).unwrap());
+        }
+        INSN_SET_I64 | INSN_SET_F64 | INSN_SET_STR | INSN_SET_BOOL => {
+            state.require(params, 3, "set", pos)?;
+            let element = element_type(instruction.insn).unwrap();
+            expect_type_on_stack(state, params, element, "set", pos)?;
+            expect_type_on_stack(state, params, Type::Int, "set", pos)?;
+            expect_type_on_stack(state, params, array_type(instruction.insn), "set", pos)?;
+        }
+        INSN_ARRAY_LEN_I64 | INSN_ARRAY_LEN_F64 | INSN_ARRAY_LEN_STR | INSN_ARRAY_LEN_BOOL => {
+            state.require(params, 1, "array-len", pos)?;
+            expect_type_on_stack(
+                state,
+                params,
+                array_type(instruction.insn),
+                "array-len",
+                pos,
+            )?;
+            state.push(Type::Int);
+        }
+        INSN_APPEND_I64 | INSN_APPEND_F64 | INSN_APPEND_STR | INSN_APPEND_BOOL => {
+            state.require(params, 2, "append", pos)?;
+            let element = element_type(instruction.insn).unwrap();
+            expect_type_on_stack(state, params, element, "append", pos)?;
+            expect_type_on_stack(state, params, array_type(instruction.insn), "append", pos)?;
+        }
+        insn => {
+            return Err(Error::other(format!(
+                "Invalid instruction 0x{insn:04X} at {pos}"
+            )));
         }
     }
-    Ok((max_stack_size, stack.len()))
+    Ok(())
+}
+
+fn local_index(operand: i64, pos: Position) -> Result<usize> {
+    usize::try_from(operand)
+        .ok()
+        .filter(|&index| index < MAX_LOCALS)
+        .ok_or_else(|| Error::other(format!("Invalid local {operand} at {pos}")))
+}
+
+/// Type of the array an array instruction works on
+fn array_type(insn: u16) -> Type {
+    element_type(insn).and_then(Type::array).unwrap()
 }
 
 fn expect_type_on_stack(
-    stack: &mut Vec<Type>,
+    state: &mut State,
+    params: &mut Params,
     type_: Type,
     insn_type: &str,
-    pos: u64,
+    pos: Position,
+) -> Result<()> {
+    let x = state.slots.pop().unwrap();
+    expect_type(x, params, type_, insn_type, pos)
+}
+
+fn expect_type(
+    x: Slot,
+    params: &mut Params,
+    type_: Type,
+    insn_type: &str,
+    pos: Position,
 ) -> Result<()> {
-    let x = stack.pop().unwrap();
-    if x != type_ {
-        return Err(Error::new(
-            ErrorKind::Other,
-            format!("Invalid type on stack in {insn_type} instruction at position 0x{pos:08X}: expected {type_:?} but found {x:?}"),
+    let matches = match x {
+        Slot::Known(x) => x == type_,
+        Slot::Param(i) => constrain(params, i, type_).is_some(),
+    };
+    if !matches {
+        let x = match x {
+            Slot::Param(i) => Slot::Known(params.type_of(i).unwrap()),
+            x => x,
+        };
+        return Err(Error::other(
+            format!("Invalid type on stack in {insn_type} instruction at {pos}: expected {type_:?} but found {x:?}"),
         ));
     }
     Ok(())
 }
 
-fn invalid_stack(insn_type: &str, pos: u64) -> String {
-    format!("Invalid stack at {insn_type} instruction at position 0x{pos:08X}")
+fn invalid_stack(insn_type: &str, pos: Position) -> String {
+    format!("Invalid stack at {insn_type} instruction at {pos}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, parser, Flags};
+
+    /// Encodes the instructions, the operand of `PUSH_ADDR` is the index of the target instruction
+    fn assemble(instructions: &[(u16, i64)]) -> Vec<u8> {
+        let mut offsets = vec![0];
+        for (insn, _) in instructions {
+            offsets.push(offsets.last().unwrap() + 2 + operand_size(*insn) as i64);
+        }
+        let mut bytes = Vec::new();
+        for &(insn, operand) in instructions {
+            bytes.extend(insn.to_le_bytes());
+            if operand_size(insn) == 8 {
+                let operand = match insn {
+                    INSN_PUSH_ADDR => offsets[operand as usize],
+                    _ => operand,
+                };
+                bytes.extend(operand.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn check_source(source: &str) -> Result<(Option<usize>, usize)> {
+        let pre_binary = parser::parse(source, &Flags::default(), &[]).unwrap();
+        check(&compiler::compile(&pre_binary, None).code, &[], &[], None)
+    }
+
+    fn error(result: Result<(Option<usize>, usize)>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn rejects_mismatched_stacks_at_join_points() {
+        let bytes = assemble(&[
+            (INSN_PUSH_BOOL, 1),
+            (INSN_PUSH_ADDR, 6),
+            (INSN_JNZ, 0),
+            (INSN_PUSH_I64, 1),
+            (INSN_PUSH_ADDR, 7),
+            (INSN_J, 0),
+            (INSN_PUSH_F64, 0),
+        ]);
+        assert!(error(check(&bytes, &[], &[], None)).starts_with("Stack mismatch at join point"));
+        // The entry taken from the caller on one path is compared to the other path as well
+        let bytes = assemble(&[
+            (INSN_PUSH_I64, 1),
+            (INSN_PUSH_BOOL, 1),
+            (INSN_PUSH_ADDR, 5),
+            (INSN_CALL, 0),
+            (INSN_ABORT, 0),
+            (INSN_PUSH_ADDR, 10),
+            (INSN_JZ, 0),
+            (INSN_DROP, 0),
+            (INSN_PUSH_ADDR, 10),
+            (INSN_J, 0),
+            (INSN_RET, 0),
+        ]);
+        assert!(error(check(&bytes, &[], &[], None)).ends_with("expected [$1] but found []"));
+    }
+
+    #[test]
+    fn rejects_jumps_without_pushed_target() {
+        let bytes = assemble(&[(INSN_PUSH_I64, 0), (INSN_NUMCONV_ADDR, 0), (INSN_J, 0)]);
+        assert!(error(check(&bytes, &[], &[], None)).starts_with("Could not determine target"));
+    }
+
+    #[test]
+    fn rejects_jumps_into_instructions() {
+        let mut bytes = assemble(&[(INSN_PUSH_I64, 0), (INSN_PUSH_ADDR, 0), (INSN_J, 0)]);
+        // Point into the immediate of the first instruction
+        bytes[12] = 2;
+        assert!(error(check(&bytes, &[], &[], None)).starts_with("Jump into the middle"));
+    }
+
+    #[test]
+    fn settles_effects_of_recursive_words() {
+        // The recursive call is skipped in the first round, its result is only known afterwards
+        let source = "fn count ( int -- int ) dup 0 > if 1 - count 1 + end end 5 count";
+        assert_eq!(check_source(source).unwrap(), (None, 1));
+        // Only the effect found in the first round shows that the result is not a float
+        let bytes = assemble(&[
+            (INSN_PUSH_I64, 5),
+            (INSN_PUSH_ADDR, 4),
+            (INSN_CALL, 0),
+            (INSN_ABORT, 0),
+            (INSN_DUP, 0),
+            (INSN_PUSH_I64, 0),
+            (INSN_GT_I64, 0),
+            (INSN_PUSH_ADDR, 12),
+            (INSN_JZ, 0),
+            (INSN_PUSH_ADDR, 4),
+            (INSN_CALL, 0),
+            (INSN_NUMCONV_I64, 0),
+            (INSN_RET, 0),
+        ]);
+        assert!(error(check(&bytes, &[], &[], None))
+            .starts_with("Invalid type on stack in numconv-int"));
+    }
+
+    #[test]
+    fn joins_params_selected_on_different_paths() {
+        let source = "fn sel ( int int bool -- int ) if drop else nip end end 1 2 true sel";
+        assert_eq!(check_source(source).unwrap().1, 1);
+        let source = "fn f ( int int bool -- int int ) if swap end end 1 2 true f";
+        assert_eq!(check_source(source).unwrap().1, 2);
+        let source = "fn first ( int int -- int ) drop end \
+            fn choose ( int int bool -- int ) if first else swap first end end 1 2 true choose";
+        assert_eq!(check_source(source).unwrap().1, 1);
+        // Without a declared signature the caller still has to pass the same types
+        let bytes = assemble(&[
+            (INSN_PUSH_I64, 1),
+            (INSN_PUSH_F64, 0),
+            (INSN_PUSH_BOOL, 1),
+            (INSN_PUSH_ADDR, 6),
+            (INSN_CALL, 0),
+            (INSN_ABORT, 0),
+            (INSN_PUSH_ADDR, 11),
+            (INSN_JZ, 0),
+            (INSN_DROP, 0),
+            (INSN_PUSH_ADDR, 13),
+            (INSN_J, 0),
+            (INSN_SWAP, 0),
+            (INSN_DROP, 0),
+            (INSN_RET, 0),
+        ]);
+        assert!(error(check(&bytes, &[], &[], None)).starts_with("Invalid type on stack in call"));
+    }
+
+    #[test]
+    fn computes_max_stack_size() {
+        assert_eq!(check_source("1 2 3 + *").unwrap(), (Some(3), 1));
+        // The call adds the stack size of the word on top of its arguments
+        let source = "fn square ( int -- int ) dup * end 1 2 square +";
+        assert_eq!(check_source(source).unwrap(), (Some(3), 1));
+    }
 }
@@ -3,69 +3,238 @@ use std::io::{Result, Write};
 use byteorder::{LittleEndian, WriteBytesExt};
 
 use crate::{
+    binary::Binary,
     bytecode::*,
+    debug::{DebugInfo, LineEntry},
     parser::{Insn, PreBinary},
 };
 
-pub fn compile(write: &mut impl Write, pre_binary: &PreBinary) -> Result<()> {
-    write.write_u64::<LittleEndian>(pre_binary.constants.len() as _)?;
-    for constant in &pre_binary.constants {
-        write.write_u64::<LittleEndian>(constant.len() as _)?;
-        write.write_all(constant.as_bytes())?;
-    }
+/// Encodes the program, debug information is only emitted if the name of the source file is given
+pub fn compile(pre_binary: &PreBinary, file: Option<&str>) -> Binary {
+    let mut code = Vec::new();
+    let mut offsets = Vec::with_capacity(pre_binary.instructions.len());
     for insn in &pre_binary.instructions {
-        match insn {
-            Insn::Drop => write.write_u16::<LittleEndian>(INSN_DROP)?,
-            Insn::Load => write.write_u16::<LittleEndian>(INSN_LOAD)?,
-            Insn::Swap => write.write_u16::<LittleEndian>(INSN_SWAP)?,
-            Insn::Dup => write.write_u16::<LittleEndian>(INSN_DUP)?,
-            Insn::Jump => write.write_u16::<LittleEndian>(INSN_J)?,
-            Insn::JumpNotZero => write.write_u16::<LittleEndian>(INSN_JNZ)?,
-            Insn::JumpZero => write.write_u16::<LittleEndian>(INSN_JZ)?,
-            Insn::Call => write.write_u16::<LittleEndian>(INSN_CALL)?,
-            Insn::PushInt(value) => {
-                write.write_u16::<LittleEndian>(INSN_PUSH_I64)?;
-                write.write_i64::<LittleEndian>(*value)?;
-            }
-            Insn::PushFloat(value) => {
-                write.write_u16::<LittleEndian>(INSN_PUSH_F64)?;
-                write.write_f64::<LittleEndian>(*value)?;
-            }
-            Insn::NumConvInt => write.write_u16::<LittleEndian>(INSN_NUMCONV_I64)?,
-            Insn::NumConvFloat => write.write_u16::<LittleEndian>(INSN_NUMCONV_F64)?,
-            Insn::TriRot => write.write_u16::<LittleEndian>(INSN_TROT)?,
-            Insn::DiDup => write.write_u16::<LittleEndian>(INSN_DDUP)?,
-            Insn::TriDup => write.write_u16::<LittleEndian>(INSN_TDUP)?,
-            Insn::Abort => write.write_u16::<LittleEndian>(INSN_ABORT)?,
-            Insn::Exit => write.write_u16::<LittleEndian>(INSN_EXIT)?,
-            Insn::Panic => write.write_u16::<LittleEndian>(INSN_PANIC)?,
-            Insn::Println => write.write_u16::<LittleEndian>(INSN_PRINTLN)?,
-            Insn::Input => write.write_u16::<LittleEndian>(INSN_INPUT)?,
-            Insn::Gc => write.write_u16::<LittleEndian>(INSN_GC)?,
-            Insn::PrintInt => write.write_u16::<LittleEndian>(INSN_PRINT_I64)?,
-            Insn::PrintFloat => write.write_u16::<LittleEndian>(INSN_PRINT_F64)?,
-            Insn::PrintString => write.write_u16::<LittleEndian>(INSN_PRINT_STR)?,
-            Insn::AddInt => write.write_u16::<LittleEndian>(INSN_ADD_I64)?,
-            Insn::AddFloat => write.write_u16::<LittleEndian>(INSN_ADD_F64)?,
-            Insn::AddString => write.write_u16::<LittleEndian>(INSN_ADD_STR)?,
-            Insn::SubInt => write.write_u16::<LittleEndian>(INSN_SUB_I64)?,
-            Insn::SubFloat => write.write_u16::<LittleEndian>(INSN_SUB_F64)?,
-            Insn::MulInt => write.write_u16::<LittleEndian>(INSN_MUL_I64)?,
-            Insn::MulFloat => write.write_u16::<LittleEndian>(INSN_MUL_F64)?,
-            Insn::DivInt => write.write_u16::<LittleEndian>(INSN_DIV_I64)?,
-            Insn::DivFloat => write.write_u16::<LittleEndian>(INSN_DIV_F64)?,
-            Insn::EqInt => write.write_u16::<LittleEndian>(INSN_EQ_I64)?,
-            Insn::LtInt => write.write_u16::<LittleEndian>(INSN_LT_I64)?,
-            Insn::GtInt => write.write_u16::<LittleEndian>(INSN_GT_I64)?,
-            Insn::LeInt => write.write_u16::<LittleEndian>(INSN_LE_I64)?,
-            Insn::GeInt => write.write_u16::<LittleEndian>(INSN_GE_I64)?,
-            Insn::EqFloat => write.write_u16::<LittleEndian>(INSN_EQ_F64)?,
-            Insn::LtFloat => write.write_u16::<LittleEndian>(INSN_LT_F64)?,
-            Insn::GtFloat => write.write_u16::<LittleEndian>(INSN_GT_F64)?,
-            Insn::LeFloat => write.write_u16::<LittleEndian>(INSN_LE_F64)?,
-            Insn::GeFloat => write.write_u16::<LittleEndian>(INSN_GE_F64)?,
-            Insn::EqString => write.write_u16::<LittleEndian>(INSN_EQ_STR)?,
+        offsets.push(code.len() as u64);
+        // Writing into a vector can not fail
+        compile_insn(&mut code, insn).unwrap();
+    }
+    let debug_info = file.map(|file| DebugInfo {
+        file: file.to_string(),
+        lines: offsets
+            .iter()
+            .zip(&pre_binary.spans)
+            .map(|(offset, span)| LineEntry {
+                offset: *offset,
+                line: span.line as _,
+                column: span.column as _,
+            })
+            .collect(),
+        labels: pre_binary
+            .labels
+            .iter()
+            .map(|(name, offset)| (name.clone(), *offset as _))
+            .collect(),
+    });
+    Binary {
+        constants: pre_binary.constants.clone(),
+        globals: pre_binary.globals.clone(),
+        code,
+        debug_info,
+        symbols: pre_binary
+            .symbols
+            .iter()
+            .map(|(name, offset)| (name.clone(), *offset as _))
+            .collect(),
+        metadata: vec![(
+            "compiler".to_string(),
+            concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_string(),
+        )],
+        imports: pre_binary.imports.clone(),
+    }
+}
+
+fn compile_insn(write: &mut impl Write, insn: &Insn) -> Result<()> {
+    match insn {
+        Insn::Drop => write.write_u16::<LittleEndian>(INSN_DROP)?,
+        Insn::Load => write.write_u16::<LittleEndian>(INSN_LOAD)?,
+        Insn::Swap => write.write_u16::<LittleEndian>(INSN_SWAP)?,
+        Insn::Dup => write.write_u16::<LittleEndian>(INSN_DUP)?,
+        Insn::Jump => write.write_u16::<LittleEndian>(INSN_J)?,
+        Insn::JumpNotZero => write.write_u16::<LittleEndian>(INSN_JNZ)?,
+        Insn::JumpZero => write.write_u16::<LittleEndian>(INSN_JZ)?,
+        Insn::Call => write.write_u16::<LittleEndian>(INSN_CALL)?,
+        Insn::Ret => write.write_u16::<LittleEndian>(INSN_RET)?,
+        Insn::Native(index) => {
+            write.write_u16::<LittleEndian>(INSN_NATIVE)?;
+            write.write_u64::<LittleEndian>(*index)?;
+        }
+        Insn::PushInt(value) => {
+            write.write_u16::<LittleEndian>(INSN_PUSH_I64)?;
+            write.write_i64::<LittleEndian>(*value)?;
+        }
+        Insn::PushFloat(value) => {
+            write.write_u16::<LittleEndian>(INSN_PUSH_F64)?;
+            write.write_f64::<LittleEndian>(*value)?;
+        }
+        Insn::PushAddress(value) => {
+            write.write_u16::<LittleEndian>(INSN_PUSH_ADDR)?;
+            write.write_i64::<LittleEndian>(*value)?;
+        }
+        Insn::PushBool(value) => {
+            write.write_u16::<LittleEndian>(INSN_PUSH_BOOL)?;
+            write.write_i64::<LittleEndian>(*value as i64)?;
         }
+        Insn::NumConvInt => write.write_u16::<LittleEndian>(INSN_NUMCONV_I64)?,
+        Insn::NumConvFloat => write.write_u16::<LittleEndian>(INSN_NUMCONV_F64)?,
+        Insn::NumConvBool => write.write_u16::<LittleEndian>(INSN_NUMCONV_BOOL)?,
+        Insn::NumConvAddress => write.write_u16::<LittleEndian>(INSN_NUMCONV_ADDR)?,
+        Insn::NumConvString => write.write_u16::<LittleEndian>(INSN_NUMCONV_STR)?,
+        Insn::FloatConvString => write.write_u16::<LittleEndian>(INSN_FLOATCONV_STR)?,
+        Insn::StrConvInt => write.write_u16::<LittleEndian>(INSN_STRCONV_I64)?,
+        Insn::StrConvFloat => write.write_u16::<LittleEndian>(INSN_STRCONV_F64)?,
+        Insn::BoolConvInt => write.write_u16::<LittleEndian>(INSN_BOOLCONV_I64)?,
+        Insn::AddrConvInt => write.write_u16::<LittleEndian>(INSN_ADDRCONV_I64)?,
+        Insn::TriRot => write.write_u16::<LittleEndian>(INSN_TROT)?,
+        Insn::DiDup => write.write_u16::<LittleEndian>(INSN_DDUP)?,
+        Insn::TriDup => write.write_u16::<LittleEndian>(INSN_TDUP)?,
+        Insn::Over => write.write_u16::<LittleEndian>(INSN_OVER)?,
+        Insn::Nip => write.write_u16::<LittleEndian>(INSN_NIP)?,
+        Insn::Tuck => write.write_u16::<LittleEndian>(INSN_TUCK)?,
+        Insn::DupPair => write.write_u16::<LittleEndian>(INSN_DUP2)?,
+        Insn::DropPair => write.write_u16::<LittleEndian>(INSN_DROP2)?,
+        Insn::SwapPair => write.write_u16::<LittleEndian>(INSN_SWAP2)?,
+        Insn::Pick(depth) => {
+            write.write_u16::<LittleEndian>(INSN_PICK)?;
+            write.write_u64::<LittleEndian>(*depth)?;
+        }
+        Insn::Roll(depth) => {
+            write.write_u16::<LittleEndian>(INSN_ROLL)?;
+            write.write_u64::<LittleEndian>(*depth)?;
+        }
+        Insn::LoadLocal(index) => {
+            write.write_u16::<LittleEndian>(INSN_LOAD_LOCAL)?;
+            write.write_u64::<LittleEndian>(*index)?;
+        }
+        Insn::StoreLocal(index) => {
+            write.write_u16::<LittleEndian>(INSN_STORE_LOCAL)?;
+            write.write_u64::<LittleEndian>(*index)?;
+        }
+        Insn::LoadGlobal(index) => {
+            write.write_u16::<LittleEndian>(INSN_GLOAD)?;
+            write.write_u64::<LittleEndian>(*index)?;
+        }
+        Insn::StoreGlobal(index) => {
+            write.write_u16::<LittleEndian>(INSN_GSTORE)?;
+            write.write_u64::<LittleEndian>(*index)?;
+        }
+        Insn::Abort => write.write_u16::<LittleEndian>(INSN_ABORT)?,
+        Insn::Exit => write.write_u16::<LittleEndian>(INSN_EXIT)?,
+        Insn::Panic => write.write_u16::<LittleEndian>(INSN_PANIC)?,
+        Insn::Println => write.write_u16::<LittleEndian>(INSN_PRINTLN)?,
+        Insn::Input => write.write_u16::<LittleEndian>(INSN_INPUT)?,
+        Insn::Gc => write.write_u16::<LittleEndian>(INSN_GC)?,
+        Insn::PrintInt => write.write_u16::<LittleEndian>(INSN_PRINT_I64)?,
+        Insn::PrintFloat => write.write_u16::<LittleEndian>(INSN_PRINT_F64)?,
+        Insn::PrintString => write.write_u16::<LittleEndian>(INSN_PRINT_STR)?,
+        Insn::PrintBool => write.write_u16::<LittleEndian>(INSN_PRINT_BOOL)?,
+        Insn::AddInt => write.write_u16::<LittleEndian>(INSN_ADD_I64)?,
+        Insn::AddFloat => write.write_u16::<LittleEndian>(INSN_ADD_F64)?,
+        Insn::AddString => write.write_u16::<LittleEndian>(INSN_ADD_STR)?,
+        Insn::ModInt => write.write_u16::<LittleEndian>(INSN_MOD_I64)?,
+        Insn::ModFloat => write.write_u16::<LittleEndian>(INSN_MOD_F64)?,
+        Insn::NegInt => write.write_u16::<LittleEndian>(INSN_NEG_I64)?,
+        Insn::NegFloat => write.write_u16::<LittleEndian>(INSN_NEG_F64)?,
+        Insn::AndInt => write.write_u16::<LittleEndian>(INSN_AND_I64)?,
+        Insn::OrInt => write.write_u16::<LittleEndian>(INSN_OR_I64)?,
+        Insn::XorInt => write.write_u16::<LittleEndian>(INSN_XOR_I64)?,
+        Insn::NotInt => write.write_u16::<LittleEndian>(INSN_NOT_I64)?,
+        Insn::ShlInt => write.write_u16::<LittleEndian>(INSN_SHL_I64)?,
+        Insn::ShrInt => write.write_u16::<LittleEndian>(INSN_SHR_I64)?,
+        Insn::AndBool => write.write_u16::<LittleEndian>(INSN_AND_BOOL)?,
+        Insn::OrBool => write.write_u16::<LittleEndian>(INSN_OR_BOOL)?,
+        Insn::NotBool => write.write_u16::<LittleEndian>(INSN_NOT_BOOL)?,
+        Insn::SubInt => write.write_u16::<LittleEndian>(INSN_SUB_I64)?,
+        Insn::SubFloat => write.write_u16::<LittleEndian>(INSN_SUB_F64)?,
+        Insn::MulInt => write.write_u16::<LittleEndian>(INSN_MUL_I64)?,
+        Insn::MulFloat => write.write_u16::<LittleEndian>(INSN_MUL_F64)?,
+        Insn::DivInt => write.write_u16::<LittleEndian>(INSN_DIV_I64)?,
+        Insn::AddWrapInt => write.write_u16::<LittleEndian>(INSN_ADD_WRAP_I64)?,
+        Insn::SubWrapInt => write.write_u16::<LittleEndian>(INSN_SUB_WRAP_I64)?,
+        Insn::MulWrapInt => write.write_u16::<LittleEndian>(INSN_MUL_WRAP_I64)?,
+        Insn::DivWrapInt => write.write_u16::<LittleEndian>(INSN_DIV_WRAP_I64)?,
+        Insn::AddSatInt => write.write_u16::<LittleEndian>(INSN_ADD_SAT_I64)?,
+        Insn::SubSatInt => write.write_u16::<LittleEndian>(INSN_SUB_SAT_I64)?,
+        Insn::MulSatInt => write.write_u16::<LittleEndian>(INSN_MUL_SAT_I64)?,
+        Insn::DivSatInt => write.write_u16::<LittleEndian>(INSN_DIV_SAT_I64)?,
+        Insn::DivFloat => write.write_u16::<LittleEndian>(INSN_DIV_F64)?,
+        Insn::EqInt => write.write_u16::<LittleEndian>(INSN_EQ_I64)?,
+        Insn::LtInt => write.write_u16::<LittleEndian>(INSN_LT_I64)?,
+        Insn::GtInt => write.write_u16::<LittleEndian>(INSN_GT_I64)?,
+        Insn::LeInt => write.write_u16::<LittleEndian>(INSN_LE_I64)?,
+        Insn::GeInt => write.write_u16::<LittleEndian>(INSN_GE_I64)?,
+        Insn::EqFloat => write.write_u16::<LittleEndian>(INSN_EQ_F64)?,
+        Insn::LtFloat => write.write_u16::<LittleEndian>(INSN_LT_F64)?,
+        Insn::GtFloat => write.write_u16::<LittleEndian>(INSN_GT_F64)?,
+        Insn::LeFloat => write.write_u16::<LittleEndian>(INSN_LE_F64)?,
+        Insn::GeFloat => write.write_u16::<LittleEndian>(INSN_GE_F64)?,
+        Insn::EqString => write.write_u16::<LittleEndian>(INSN_EQ_STR)?,
+        Insn::LtString => write.write_u16::<LittleEndian>(INSN_LT_STR)?,
+        Insn::GtString => write.write_u16::<LittleEndian>(INSN_GT_STR)?,
+        Insn::LenString => write.write_u16::<LittleEndian>(INSN_LEN_STR)?,
+        Insn::SubstrString => write.write_u16::<LittleEndian>(INSN_SUBSTR_STR)?,
+        Insn::FindString => write.write_u16::<LittleEndian>(INSN_FIND_STR)?,
+        Insn::CharAtString => write.write_u16::<LittleEndian>(INSN_CHAR_AT_STR)?,
+        Insn::SplitFirstString => write.write_u16::<LittleEndian>(INSN_SPLIT_FIRST_STR)?,
+        Insn::TrimString => write.write_u16::<LittleEndian>(INSN_TRIM_STR)?,
+        Insn::UpperString => write.write_u16::<LittleEndian>(INSN_UPPER_STR)?,
+        Insn::LowerString => write.write_u16::<LittleEndian>(INSN_LOWER_STR)?,
+        Insn::StartsWithString => write.write_u16::<LittleEndian>(INSN_STARTS_WITH_STR)?,
+        Insn::NewArray(element) => write.write_u16::<LittleEndian>(typed(
+            *element,
+            [
+                INSN_NEW_ARRAY_I64,
+                INSN_NEW_ARRAY_F64,
+                INSN_NEW_ARRAY_STR,
+                INSN_NEW_ARRAY_BOOL,
+            ],
+        ))?,
+        Insn::GetArray(element) => write.write_u16::<LittleEndian>(typed(
+            *element,
+            [INSN_GET_I64, INSN_GET_F64, INSN_GET_STR, INSN_GET_BOOL],
+        ))?,
+        Insn::SetArray(element) => write.write_u16::<LittleEndian>(typed(
+            *element,
+            [INSN_SET_I64, INSN_SET_F64, INSN_SET_STR, INSN_SET_BOOL],
+        ))?,
+        Insn::LenArray(element) => write.write_u16::<LittleEndian>(typed(
+            *element,
+            [
+                INSN_ARRAY_LEN_I64,
+                INSN_ARRAY_LEN_F64,
+                INSN_ARRAY_LEN_STR,
+                INSN_ARRAY_LEN_BOOL,
+            ],
+        ))?,
+        Insn::PushArray(element) => write.write_u16::<LittleEndian>(typed(
+            *element,
+            [
+                INSN_APPEND_I64,
+                INSN_APPEND_F64,
+                INSN_APPEND_STR,
+                INSN_APPEND_BOOL,
+            ],
+        ))?,
     }
     Ok(())
 }
+
+/// Selects the variant of an array instruction for the type of the elements, the variants are
+/// ordered int, float, string and bool
+fn typed(element: Type, insns: [u16; 4]) -> u16 {
+    match element {
+        Type::Int => insns[0],
+        Type::Float => insns[1],
+        Type::String => insns[2],
+        _ => insns[3],
+    }
+}
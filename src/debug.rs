@@ -0,0 +1,82 @@
+use std::io::{Read, Result, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::binary::{read_string, write_string};
+
+/// Source location of the instruction at an offset in the code
+#[derive(Clone)]
+pub struct LineEntry {
+    pub offset: u64,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Maps offsets in the code back to the source
+#[derive(Clone)]
+pub struct DebugInfo {
+    pub file: String,
+    /// Sorted by offset
+    pub lines: Vec<LineEntry>,
+    /// Sorted by offset
+    pub labels: Vec<(String, u64)>,
+}
+
+impl DebugInfo {
+    /// Formats the source location of the instruction at the offset like `file.roth:42:7 in :label`
+    pub fn location(&self, offset: u64) -> Option<String> {
+        let i = self.lines.partition_point(|entry| entry.offset <= offset);
+        let entry = self.lines.get(i.checked_sub(1)?)?;
+        let mut location = format!("{}:{}:{}", self.file, entry.line, entry.column);
+        if let Some(label) = self.label(offset) {
+            location += &format!(" in :{label}");
+        }
+        Some(location)
+    }
+
+    /// Returns the name of the last label at or before the offset
+    pub fn label(&self, offset: u64) -> Option<&str> {
+        let i = self.labels.partition_point(|(_, label)| *label <= offset);
+        Some(self.labels.get(i.checked_sub(1)?)?.0.as_str())
+    }
+
+    pub fn write(&self, write: &mut impl Write) -> Result<()> {
+        write_string(write, &self.file)?;
+        write.write_u64::<LittleEndian>(self.lines.len() as _)?;
+        for entry in &self.lines {
+            write.write_u64::<LittleEndian>(entry.offset)?;
+            write.write_u32::<LittleEndian>(entry.line)?;
+            write.write_u32::<LittleEndian>(entry.column)?;
+        }
+        write.write_u64::<LittleEndian>(self.labels.len() as _)?;
+        for (name, offset) in &self.labels {
+            write_string(write, name)?;
+            write.write_u64::<LittleEndian>(*offset)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(read: &mut impl Read) -> Result<Self> {
+        let file = read_string(read)?;
+        let len = read.read_u64::<LittleEndian>()?;
+        let mut lines = Vec::new();
+        for _ in 0..len {
+            lines.push(LineEntry {
+                offset: read.read_u64::<LittleEndian>()?,
+                line: read.read_u32::<LittleEndian>()?,
+                column: read.read_u32::<LittleEndian>()?,
+            });
+        }
+        let len = read.read_u64::<LittleEndian>()?;
+        let mut labels = Vec::new();
+        for _ in 0..len {
+            let name = read_string(read)?;
+            labels.push((name, read.read_u64::<LittleEndian>()?));
+        }
+        Ok(Self {
+            file,
+            lines,
+            labels,
+        })
+    }
+}
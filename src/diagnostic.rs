@@ -0,0 +1,64 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Location of a token in the source, lines and columns start at 1
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Name of the source file, if it is known
+    pub file: Option<String>,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            file: None,
+            span,
+        }
+    }
+
+    /// Renders the diagnostic together with the offending line of the source
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let Span { line, column, len } = self.span;
+        let number = line.to_string();
+        let indent = " ".repeat(number.len());
+        let mut out = format!(
+            "error: {}\n{indent}--> {file}:{line}:{column}\n",
+            self.message
+        );
+        if let Some(text) = source.lines().nth(line.wrapping_sub(1)) {
+            let text = text.trim_end_matches('\r');
+            let prefix: String = text
+                .chars()
+                .take(column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out += &format!(
+                "{indent} |\n{number} | {text}\n{indent} | {prefix}{}\n",
+                "^".repeat(len.max(1))
+            );
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    io::{Result, Write},
+};
+
+use crate::{binary::Binary, bytecode::*};
+
+/// Writes a readable listing of the binary.
+///
+/// Every instruction is printed with its offset, mnemonic and immediate. Pushed code
+/// addresses are shown symbolically if a label or word starts at the target.
+pub fn disassemble(write: &mut impl Write, binary: &Binary) -> Result<()> {
+    let mut names: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    let labels = binary
+        .debug_info
+        .iter()
+        .flat_map(|debug_info| &debug_info.labels);
+    for (name, offset) in labels.chain(&binary.symbols) {
+        let entry = names.entry(*offset).or_default();
+        if !entry.contains(&name.as_str()) {
+            entry.push(name);
+        }
+    }
+
+    for (key, value) in &binary.metadata {
+        writeln!(write, "; {key}: {value}")?;
+    }
+    if let Some(debug_info) = &binary.debug_info {
+        writeln!(write, "; source: {}", debug_info.file)?;
+    }
+    writeln!(write, "\n; constants")?;
+    for (i, constant) in binary.constants.iter().enumerate() {
+        writeln!(write, "{i:>8}  {constant:?}")?;
+    }
+    if !binary.globals.is_empty() {
+        writeln!(write, "\n; globals")?;
+        for (i, type_) in binary.globals.iter().enumerate() {
+            writeln!(write, "{i:>8}  {type_:?}")?;
+        }
+    }
+    if !binary.imports.is_empty() {
+        writeln!(write, "\n; imports")?;
+        for (i, (name, signature)) in binary.imports.iter().enumerate() {
+            writeln!(
+                write,
+                "{i:>8}  {name} ( {:?} -- {:?} )",
+                signature.inputs, signature.outputs
+            )?;
+        }
+    }
+    writeln!(write, "\n; code")?;
+
+    let code = &binary.code;
+    let mut pc = 0;
+    while pc < code.len() {
+        for name in names.get(&(pc as u64)).into_iter().flatten() {
+            writeln!(write, ":{name}")?;
+        }
+        if pc + 2 > code.len() {
+            writeln!(write, "{pc:08X}  .byte 0x{:02X}", code[pc])?;
+            break;
+        }
+        let insn = u16::from_le_bytes([code[pc], code[pc + 1]]);
+        let start = pc;
+        pc += 2;
+        let Some(mnemonic) = mnemonic(insn) else {
+            writeln!(write, "{start:08X}  .insn 0x{insn:04X}")?;
+            continue;
+        };
+        let size = operand_size(insn);
+        if pc + size > code.len() {
+            writeln!(write, "{start:08X}  {mnemonic} <truncated>")?;
+            break;
+        }
+        let operand = &code[pc..pc + size];
+        pc += size;
+        match insn {
+            INSN_PUSH_I64 => {
+                let value = i64::from_le_bytes(operand.try_into().unwrap());
+                let next = code
+                    .get(pc..pc + 2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+                let comment = match next {
+                    Some(INSN_LOAD) => usize::try_from(value)
+                        .ok()
+                        .and_then(|i| binary.constants.get(i))
+                        .map(|constant| format!("{constant:?}")),
+                    _ => None,
+                };
+                match comment {
+                    Some(comment) => {
+                        writeln!(write, "{start:08X}  {mnemonic:<12}{value:<20} ; {comment}")?
+                    }
+                    None => writeln!(write, "{start:08X}  {mnemonic:<12}{value}")?,
+                }
+            }
+            INSN_PUSH_ADDR => {
+                let value = i64::from_le_bytes(operand.try_into().unwrap());
+                match names.get(&(value as u64)).and_then(|names| names.first()) {
+                    Some(name) => {
+                        writeln!(write, "{start:08X}  {mnemonic:<12}{value:<20} ; -> :{name}")?
+                    }
+                    None => writeln!(write, "{start:08X}  {mnemonic:<12}{value}")?,
+                }
+            }
+            INSN_PICK | INSN_ROLL => {
+                let depth = u64::from_le_bytes(operand.try_into().unwrap());
+                writeln!(write, "{start:08X}  {mnemonic:<12}{depth}")?;
+            }
+            INSN_LOAD_LOCAL | INSN_STORE_LOCAL | INSN_GLOAD | INSN_GSTORE => {
+                let index = u64::from_le_bytes(operand.try_into().unwrap());
+                writeln!(write, "{start:08X}  {mnemonic:<12}{index}")?;
+            }
+            INSN_NATIVE => {
+                let index = u64::from_le_bytes(operand.try_into().unwrap());
+                match binary.imports.get(index as usize) {
+                    Some((name, _)) => {
+                        writeln!(write, "{start:08X}  {mnemonic:<12}{index:<20} ; {name}")?
+                    }
+                    None => writeln!(write, "{start:08X}  {mnemonic:<12}{index}")?,
+                }
+            }
+            INSN_PUSH_F64 => {
+                let value = f64::from_le_bytes(operand.try_into().unwrap());
+                writeln!(write, "{start:08X}  {mnemonic:<12}{value:?}")?;
+            }
+            INSN_PUSH_BOOL => {
+                let value = i64::from_le_bytes(operand.try_into().unwrap()) != 0;
+                writeln!(write, "{start:08X}  {mnemonic:<12}{value}")?;
+            }
+            _ => writeln!(write, "{start:08X}  {mnemonic}")?,
+        }
+    }
+    for name in names.get(&(code.len() as u64)).into_iter().flatten() {
+        writeln!(write, ":{name}")?;
+    }
+    Ok(())
+}
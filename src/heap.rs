@@ -0,0 +1,276 @@
+use std::mem::size_of;
+
+use crate::{bytecode::Type, runtime::Value};
+
+/// Reference to a string or an array on the heap.
+///
+/// The generation tells apart objects that occupied the same entry, so a handle to a collected
+/// object never resolves to a newer one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Handle {
+    pub index: u32,
+    pub generation: u32,
+}
+
+#[derive(Clone, Copy)]
+pub struct HeapConfig {
+    /// Size of the objects in bytes at which the first collection starts
+    pub initial_size: usize,
+    /// After a collection the next one starts when the heap grew by this factor, which has to be
+    /// greater than 1
+    pub growth_factor: f64,
+    /// Size of the objects in bytes the heap must not exceed, `None` if unlimited
+    pub max_size: Option<usize>,
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        Self {
+            initial_size: 1 << 20,
+            growth_factor: 2.0,
+            max_size: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub bytes_freed: usize,
+    /// Largest size of the objects on the heap in bytes
+    pub peak_size: usize,
+}
+
+/// Bytes every object takes besides its contents, so empty objects still fill up the heap
+pub const OBJECT_OVERHEAD: usize = size_of::<Entry>();
+
+enum Object {
+    String(String),
+    /// Elements that are strings are kept alive by the array
+    Array {
+        elements: Vec<Value>,
+        element: Type,
+    },
+}
+
+impl Object {
+    fn size(&self) -> usize {
+        OBJECT_OVERHEAD
+            + match self {
+                Object::String(value) => value.len(),
+                Object::Array { elements, .. } => elements.len() * size_of::<Value>(),
+            }
+    }
+}
+
+struct Entry {
+    generation: u32,
+    value: Option<Object>,
+    marked: bool,
+}
+
+/// Strings and arrays of the virtual machine, referenced by handles that stay valid across
+/// allocations
+pub struct Heap {
+    entries: Vec<Entry>,
+    /// Amount of entries at the start that are never collected
+    pinned: usize,
+    /// Indices of entries without an object
+    free: Vec<u32>,
+    /// Size of the objects that are not pinned in bytes
+    size: usize,
+    /// Size at which the next collection starts
+    threshold: usize,
+    config: HeapConfig,
+    stats: GcStats,
+}
+
+impl Heap {
+    /// Creates a heap holding the constants, the handle of a constant has its index and generation 0
+    pub fn new(constants: Vec<String>, config: HeapConfig) -> Self {
+        Self {
+            pinned: constants.len(),
+            entries: constants
+                .into_iter()
+                .map(|value| Entry {
+                    generation: 0,
+                    value: Some(Object::String(value)),
+                    marked: false,
+                })
+                .collect(),
+            free: Vec::new(),
+            size: 0,
+            threshold: config.initial_size,
+            config,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Returns the handle of the constant with the index
+    pub fn constant(&self, index: usize) -> Option<Handle> {
+        (index < self.pinned).then_some(Handle {
+            index: index as _,
+            generation: 0,
+        })
+    }
+
+    /// Returns whether a collection should run before allocating an object of the size, which is
+    /// also the case if the allocation would exceed the maximum size
+    pub fn needs_collection(&self, size: usize) -> bool {
+        let limit = self
+            .config
+            .max_size
+            .map_or(self.threshold, |max_size| max_size.min(self.threshold));
+        self.size + size > limit
+    }
+
+    /// Stores the string, `None` if the heap would exceed its maximum size
+    pub fn alloc(&mut self, value: String) -> Option<Handle> {
+        self.alloc_object(Object::String(value))
+    }
+
+    /// Creates an empty array with elements of the type, `None` if the heap would exceed its
+    /// maximum size
+    pub fn alloc_array(&mut self, element: Type) -> Option<Handle> {
+        self.alloc_object(Object::Array {
+            elements: Vec::new(),
+            element,
+        })
+    }
+
+    fn alloc_object(&mut self, value: Object) -> Option<Handle> {
+        if !self.grow(value.size()) {
+            return None;
+        }
+        let index = match self.free.pop() {
+            Some(index) => index as usize,
+            None => {
+                self.entries.push(Entry {
+                    generation: 0,
+                    value: None,
+                    marked: false,
+                });
+                self.entries.len() - 1
+            }
+        };
+        let entry = &mut self.entries[index];
+        entry.value = Some(value);
+        Some(Handle {
+            index: index as _,
+            generation: entry.generation,
+        })
+    }
+
+    /// Adds the size to the heap, returns false if it would exceed its maximum size
+    fn grow(&mut self, size: usize) -> bool {
+        let size = self.size + size;
+        if self.config.max_size.is_some_and(|max_size| size > max_size) {
+            return false;
+        }
+        self.size = size;
+        self.stats.peak_size = self.stats.peak_size.max(size);
+        true
+    }
+
+    fn object(&self, handle: Handle) -> Option<&Object> {
+        let entry = self.entries.get(handle.index as usize)?;
+        if entry.generation != handle.generation {
+            return None;
+        }
+        entry.value.as_ref()
+    }
+
+    fn object_mut(&mut self, handle: Handle) -> Option<&mut Object> {
+        let entry = self.entries.get_mut(handle.index as usize)?;
+        if entry.generation != handle.generation {
+            return None;
+        }
+        entry.value.as_mut()
+    }
+
+    /// Returns the string, `None` if the handle is invalid or does not belong to a string
+    pub fn get(&self, handle: Handle) -> Option<&String> {
+        match self.object(handle)? {
+            Object::String(value) => Some(value),
+            Object::Array { .. } => None,
+        }
+    }
+
+    /// Returns the elements of the array, `None` if the handle is invalid or does not belong to an
+    /// array
+    pub fn array(&self, handle: Handle) -> Option<&[Value]> {
+        match self.object(handle)? {
+            Object::Array { elements, .. } => Some(elements),
+            Object::String(_) => None,
+        }
+    }
+
+    pub fn array_mut(&mut self, handle: Handle) -> Option<&mut [Value]> {
+        match self.object_mut(handle)? {
+            Object::Array { elements, .. } => Some(elements),
+            Object::String(_) => None,
+        }
+    }
+
+    /// Appends the value to the array, returns false if the heap would exceed its maximum size or
+    /// the handle does not belong to an array
+    pub fn push(&mut self, handle: Handle, value: Value) -> bool {
+        if self.array(handle).is_none() || !self.grow(size_of::<Value>()) {
+            return false;
+        }
+        if let Some(Object::Array { elements, .. }) = self.object_mut(handle) {
+            elements.push(value);
+        }
+        true
+    }
+
+    /// Frees all objects that are not reachable from the roots.
+    ///
+    /// Roots that are not valid handles are ignored, so they can be taken from untyped memory.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Handle>) {
+        let mut worklist: Vec<_> = roots.into_iter().collect();
+        while let Some(handle) = worklist.pop() {
+            if (handle.index as usize) < self.pinned || self.object(handle).is_none() {
+                continue;
+            }
+            let entry = &mut self.entries[handle.index as usize];
+            if entry.marked {
+                continue;
+            }
+            entry.marked = true;
+            if let Some(Object::Array { elements, element }) = &entry.value {
+                if element.is_string() {
+                    worklist.extend(elements.iter().map(|value| unsafe { value.string }));
+                }
+            }
+        }
+        let mut freed = 0;
+        for (index, entry) in self.entries.iter_mut().enumerate().skip(self.pinned) {
+            if !entry.marked {
+                if let Some(value) = entry.value.take() {
+                    freed += value.size();
+                    entry.generation = entry.generation.wrapping_add(1);
+                    self.free.push(index as _);
+                }
+            }
+            entry.marked = false;
+        }
+        self.size -= freed;
+        self.threshold = self
+            .config
+            .initial_size
+            .max((self.size as f64 * self.config.growth_factor) as usize);
+        self.stats.collections += 1;
+        self.stats.bytes_freed += freed;
+    }
+
+    /// Size of the objects that are not constants in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+}
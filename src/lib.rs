@@ -0,0 +1,105 @@
+use std::io::{Cursor, Error, Result, Write};
+
+use binary::Binary;
+
+pub use bytecode::{Signature, Type};
+pub use diagnostic::{Diagnostic, Span};
+pub use heap::{GcStats, HeapConfig};
+pub use runtime::{ExitStatus, NativeStack, Runtime, VmError};
+
+pub(crate) mod binary;
+pub(crate) mod bytecode;
+pub(crate) mod checker;
+pub(crate) mod compiler;
+pub(crate) mod debug;
+pub(crate) mod diagnostic;
+pub(crate) mod disasm;
+pub(crate) mod heap;
+pub(crate) mod parser;
+pub(crate) mod runtime;
+
+pub struct Flags {
+    pub verify: bool,
+    pub heap: HeapConfig,
+    pub checked_jumps: bool,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Self {
+            verify: true,
+            heap: HeapConfig::default(),
+            checked_jumps: false,
+        }
+    }
+}
+
+/// A compiled program that can be saved, loaded and run
+pub struct Program {
+    binary: Binary,
+}
+
+impl Program {
+    /// Compiles the source, debug information is only emitted if the name of the source file is
+    /// given, which is also named by errors. `natives` are the words the host registers on the
+    /// runtime.
+    pub fn compile(
+        source: &str,
+        file: Option<&str>,
+        flags: &Flags,
+        natives: &[(String, Signature)],
+    ) -> std::result::Result<Self, Diagnostic> {
+        let pre_binary = parser::parse(source, flags, natives).map_err(|mut diagnostic| {
+            diagnostic.file = file.map(str::to_string);
+            diagnostic
+        })?;
+        Ok(Self {
+            binary: compiler::compile(&pre_binary, file),
+        })
+    }
+
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        Ok(Self {
+            binary: Binary::read(&mut Cursor::new(bytes))?,
+        })
+    }
+
+    pub fn write(&self, write: &mut impl Write) -> Result<()> {
+        self.binary.write(write)
+    }
+
+    /// Writes a listing of the code, labels and the source file are only shown if the program has
+    /// debug information
+    pub fn disassemble(&self, write: &mut impl Write) -> Result<()> {
+        disasm::disassemble(write, &self.binary)
+    }
+
+    /// Checks the bytecode and returns the maximum amount of values on the stack, `None` if it is
+    /// unbounded
+    pub fn verify(&self) -> Result<Option<usize>> {
+        Ok(checker::check(
+            &self.binary.code,
+            &self.binary.imports,
+            &self.binary.globals,
+            self.binary.debug_info.as_ref(),
+        )?
+        .0)
+    }
+
+    /// Creates a runtime for the program, the bytecode is verified first if enabled in the flags.
+    /// Accesses to the stack and the code are only unchecked if the verifier bounded them.
+    pub fn runtime(&self, flags: Flags) -> Result<Runtime<'_>> {
+        let max_stack_size = if flags.verify { self.verify()? } else { None };
+        Runtime::new(
+            &self.binary.code,
+            0,
+            max_stack_size,
+            self.binary.constants.clone(),
+            &self.binary.globals,
+            self.binary.imports.clone(),
+            self.binary.debug_info.clone(),
+            flags,
+        )
+        .map_err(Error::other)
+    }
+}
@@ -1,32 +1,20 @@
 use std::{
     env::args,
     fs::{self, File},
-    io::{Cursor, Read},
+    io::{stdout, ErrorKind},
     process::exit,
+    str::FromStr,
 };
 
-use runtime::Runtime;
-use util::read_string_constants;
+use cacas::{Flags, Program};
 
-pub mod bytecode;
-pub mod checker;
-pub mod compiler;
-pub mod parser;
-pub mod runtime;
-pub mod util;
+mod util;
 
-pub struct Flags {
-    pub verify: bool,
-    pub prealloc: usize,
-}
-
-impl Default for Flags {
-    fn default() -> Self {
-        Self {
-            verify: true,
-            prealloc: 8,
-        }
-    }
+/// Flags that only concern the command line tool
+#[derive(Default)]
+struct Options {
+    debug: bool,
+    gc_stats: bool,
 }
 
 fn main() {
@@ -42,8 +30,9 @@ fn main() {
                 return;
             }
             let mut flags = Flags::default();
+            let mut options = Options::default();
             if args.len() > 4 {
-                parse_flags(&mut flags, &args[4..]);
+                parse_flags(&mut flags, &mut options, &args[4..]);
             }
             let source = fs::read_to_string(&args[2]);
             if let Err(err) = source {
@@ -55,109 +44,61 @@ fn main() {
                 println!("Could not create target file: {err}");
                 return;
             }
-            let parse_result = parser::parse(source.unwrap().as_str(), &flags);
-            if let Err(err) = parse_result {
-                println!("Could not parse: {err}");
+            let source = source.unwrap();
+            let program = Program::compile(
+                &source,
+                options.debug.then_some(args[2].as_str()),
+                &flags,
+                &[],
+            );
+            if let Err(err) = program {
+                print!("{}", err.render(&args[2], &source));
                 return;
             }
-            if let Err(err) = compiler::compile(&mut target.unwrap(), &parse_result.unwrap()) {
+            if let Err(err) = program.unwrap().write(&mut target.unwrap()) {
                 println!("Could not compile: {err}");
             };
         }
         "run" | "r" => {
-            if args.len() < 3 {
-                help();
-                return;
-            }
             let mut flags = Flags::default();
+            let mut options = Options::default();
             if args.len() > 3 {
-                parse_flags(&mut flags, &args[3..]);
-            }
-            let fio = File::open(&args[2]);
-            if let Err(err) = fio {
-                println!("Could not open file: {err}");
-                return;
+                parse_flags(&mut flags, &mut options, &args[3..]);
             }
-            let mut fio = fio.unwrap();
-            let constants = read_string_constants(&mut fio);
-            if let Err(err) = constants {
-                println!("Could not read constants: {err}");
-                return;
-            }
-            let mut bytes = Vec::new();
-            if let Err(err) = fio.read_to_end(&mut bytes) {
-                println!("Could not read file: {err}");
+            let Some(program) = load(&args[2]) else {
                 return;
             };
-            let mut vm = Runtime::new(
-                &bytes,
-                0,
-                if flags.verify {
-                    let check = checker::check(&bytes);
-                    if let Err(err) = check {
-                        println!("Invalid bytecode: {err}");
-                        return;
-                    }
-                    let check = check.unwrap();
-                    check.0
-                } else {
-                    4096 * 16
-                },
-                util::default_panic_handler,
-                constants.unwrap(),
-                flags,
-            );
-            vm.execute();
+            run(&program, flags, &options);
         }
         "interpret" | "i" => {
-            if args.len() < 3 {
-                help();
-                return;
-            }
             let mut flags = Flags::default();
+            let mut options = Options::default();
             if args.len() > 3 {
-                parse_flags(&mut flags, &args[3..]);
+                parse_flags(&mut flags, &mut options, &args[3..]);
             }
             let source = fs::read_to_string(&args[2]);
             if let Err(err) = source {
                 println!("Could not read source file: {err}");
                 return;
             }
-            let parse_result = parser::parse(source.unwrap().as_str(), &flags);
-            if let Err(err) = parse_result {
-                println!("Could not parse: {err}");
+            let source = source.unwrap();
+            let program = Program::compile(&source, Some(&args[2]), &flags, &[]);
+            if let Err(err) = program {
+                print!("{}", err.render(&args[2], &source));
                 return;
             }
-            let mut bytes = Vec::new();
-            if let Err(err) = compiler::compile(&mut bytes, &parse_result.unwrap()) {
-                println!("Could not compile: {err}");
+            run(&program.unwrap(), flags, &options);
+        }
+        "disasm" | "d" => {
+            let Some(program) = load(&args[2]) else {
                 return;
             };
-            let mut read = Cursor::new(&bytes);
-            let constants = read_string_constants(&mut read);
-            if let Err(err) = constants {
-                println!("Could not read constants: {err}");
-                return;
+            match program.disassemble(&mut stdout().lock()) {
+                // The reader of the listing stopped early, like `head`
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => {}
+                Err(err) => println!("Could not disassemble: {err}"),
+                Ok(()) => {}
             }
-            let stack_size = if flags.verify {
-                let check = checker::check(&bytes[read.position() as usize..]);
-                if let Err(err) = check {
-                    println!("Invalid bytecode: {err}");
-                    return;
-                }
-                check.unwrap().0.max(4096)
-            } else {
-                4096 * 16
-            };
-            let mut vm = Runtime::new(
-                &bytes[read.position() as usize..],
-                0,
-                stack_size,
-                util::default_panic_handler,
-                constants.unwrap(),
-                flags,
-            );
-            vm.execute();
         }
         _ => {
             help();
@@ -165,38 +106,94 @@ fn main() {
     }
 }
 
+fn load(path: &str) -> Option<Program> {
+    let bytes = fs::read(path);
+    if let Err(err) = bytes {
+        println!("Could not open file: {err}");
+        return None;
+    }
+    let program = Program::load(&bytes.unwrap());
+    if let Err(err) = program {
+        println!("Could not load binary: {err}");
+        return None;
+    }
+    program.ok()
+}
+
+fn run(program: &Program, flags: Flags, options: &Options) {
+    let vm = program.runtime(flags);
+    if let Err(err) = vm {
+        println!("Invalid bytecode: {err}");
+        return;
+    }
+    let mut vm = vm.unwrap();
+    let result = vm.execute();
+    let code = util::report(&vm, result);
+    if options.gc_stats {
+        util::print_gc_stats(&vm.gc_stats());
+    }
+    exit(code);
+}
+
 fn help() {
     println!(
         r#"Subcommands:
 compile, c    [source file] [target file] [flags]  Compile file to binary
 run, r        [file] [flags]                       Run compiled binary
 interpret, i  [source file] [flags]                Run file directly
+disasm, d     [file]                               Print listing of compiled binary
 
 Flags:
 -verify             Enable full verification
 -noverify           Disable some amount of verification
--prealloc [amount]  Set size of preallocated memory for strings"#
+-heap [bytes]       Set size of strings at which garbage is first collected, also
+                    accepted as -prealloc
+-heapgrowth [factor]
+                    Set growth of the heap until the next collection, greater
+                    than 1
+-maxheap [bytes]    Set maximum size of strings
+-gcstats            Print garbage collection statistics after running
+-checkjumps         Panic on jumps that do not target an instruction
+-debug              Emit debug information when compiling"#
     );
 }
 
-fn parse_flags(flags: &mut Flags, args: &[String]) {
+fn parse_flags(flags: &mut Flags, options: &mut Options, args: &[String]) {
     let mut iter = args.iter();
     while let Some(flag) = iter.next() {
         match flag.as_str() {
             "-verify" => flags.verify = true,
             "-noverify" => flags.verify = false,
-            "-prealloc" => {
-                let Some(amount) = iter.next() else {
-                    help();
-                    exit(-1);
-                };
-                let Ok(amount) = amount.parse() else {
+            "-checkjumps" => flags.checked_jumps = true,
+            "-debug" => options.debug = true,
+            "-gcstats" => options.gc_stats = true,
+            // `-prealloc` set the memory preallocated for strings, which became the initial heap size
+            "-heap" | "-prealloc" => flags.heap.initial_size = flag_value(iter.next()),
+            "-heapgrowth" => {
+                flags.heap.growth_factor = flag_value(iter.next());
+                // The heap has to grow between collections, NaN is rejected as well
+                if flags.heap.growth_factor.is_nan() || flags.heap.growth_factor <= 1.0 {
                     help();
                     exit(-1);
-                };
-                flags.prealloc = amount;
+                }
+            }
+            "-maxheap" => flags.heap.max_size = Some(flag_value(iter.next())),
+            _ => {
+                help();
+                exit(-1);
             }
-            _ => {}
         }
     }
 }
+
+fn flag_value<T: FromStr>(value: Option<&String>) -> T {
+    let Some(value) = value else {
+        help();
+        exit(-1);
+    };
+    let Ok(value) = value.parse() else {
+        help();
+        exit(-1);
+    };
+    value
+}
@@ -1,13 +1,24 @@
-use std::{
-    collections::HashMap,
-    io::{Error, ErrorKind, Result},
-};
+use std::{collections::HashMap, iter::Peekable, mem, str::Chars};
 
-use crate::{bytecode::Type, Flags};
+use crate::{
+    bytecode::{Signature, Type, MAX_LOCALS},
+    diagnostic::{Diagnostic, Span},
+    Flags,
+};
 
 pub struct PreBinary {
     pub constants: Vec<String>,
+    /// Types of the global variables
+    pub globals: Vec<Type>,
     pub instructions: Vec<Insn>,
+    /// Source location of every instruction
+    pub spans: Vec<Span>,
+    /// Names of labels and words with their byte offsets
+    pub labels: Vec<(String, usize)>,
+    /// Names of words with their byte offsets
+    pub symbols: Vec<(String, usize)>,
+    /// Native words used by the program
+    pub imports: Vec<(String, Signature)>,
 }
 
 pub enum Insn {
@@ -19,13 +30,37 @@ pub enum Insn {
     JumpNotZero,
     JumpZero,
     Call,
+    Ret,
+    Native(u64),
     PushInt(i64),
+    PushAddress(i64),
     PushFloat(f64),
+    PushBool(bool),
     NumConvInt,
     NumConvFloat,
+    NumConvBool,
+    NumConvAddress,
+    NumConvString,
+    FloatConvString,
+    StrConvInt,
+    StrConvFloat,
+    BoolConvInt,
+    AddrConvInt,
     TriRot,
     DiDup,
     TriDup,
+    Over,
+    Nip,
+    Tuck,
+    DupPair,
+    DropPair,
+    SwapPair,
+    Pick(u64),
+    Roll(u64),
+    LoadLocal(u64),
+    StoreLocal(u64),
+    LoadGlobal(u64),
+    StoreGlobal(u64),
     Abort,
     Exit,
     Panic,
@@ -35,15 +70,37 @@ pub enum Insn {
     PrintInt,
     PrintFloat,
     PrintString,
+    PrintBool,
     AddInt,
     SubInt,
     MulInt,
     DivInt,
+    AddWrapInt,
+    SubWrapInt,
+    MulWrapInt,
+    DivWrapInt,
+    AddSatInt,
+    SubSatInt,
+    MulSatInt,
+    DivSatInt,
     AddFloat,
     SubFloat,
     MulFloat,
     DivFloat,
     AddString,
+    ModInt,
+    ModFloat,
+    NegInt,
+    NegFloat,
+    AndInt,
+    OrInt,
+    XorInt,
+    NotInt,
+    ShlInt,
+    ShrInt,
+    AndBool,
+    OrBool,
+    NotBool,
     EqInt,
     LtInt,
     GtInt,
@@ -55,107 +112,575 @@ pub enum Insn {
     LeFloat,
     GeFloat,
     EqString,
+    LtString,
+    GtString,
+    LenString,
+    SubstrString,
+    FindString,
+    CharAtString,
+    SplitFirstString,
+    TrimString,
+    UpperString,
+    LowerString,
+    StartsWithString,
+    NewArray(Type),
+    GetArray(Type),
+    SetArray(Type),
+    LenArray(Type),
+    PushArray(Type),
 }
 
 enum PostProc {
-    InsertLabelAddress { index: usize, label: String },
+    InsertLabelAddress {
+        index: usize,
+        label: String,
+        span: Span,
+    },
 }
 
-pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
-    let mut tokens = Vec::new();
-    let mut token = String::new();
-    let pre_code = source.replace('\r', "");
-    let mut chars = pre_code.chars().peekable();
-    while let Some(c) = chars.next() {
-        match c {
-            '#' => {
-                if !token.is_empty() {
-                    tokens.push(token);
-                    token = String::new();
+struct Token {
+    text: String,
+    span: Span,
+}
+
+/// Characters of the source that keep track of their position
+struct Source<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+/// Words that cannot be used as names of word definitions and variables
+const RESERVED: &[&str] = &[
+    "fn", "if", "else", "while", "do", "end", "let", "var", "true", "false", "(", "--", ")",
+];
+
+/// Words of the language that cannot be redefined
+const BUILTINS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "mod",
+    "neg",
+    "and",
+    "or",
+    "xor",
+    "shl",
+    "shr",
+    "not",
+    "=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "wrap+",
+    "wrap-",
+    "wrap*",
+    "wrap/",
+    "sat+",
+    "sat-",
+    "sat*",
+    "sat/",
+    "len",
+    "new-array",
+    "get",
+    "set",
+    "push",
+    "substr",
+    "char-at",
+    "find",
+    "starts-with",
+    "split-first",
+    "trim",
+    "upper",
+    "lower",
+    "drop",
+    "load",
+    "swap",
+    "tRot",
+    "dup",
+    "dDup",
+    "tDup",
+    "over",
+    "nip",
+    "tuck",
+    "2dup",
+    "2drop",
+    "2swap",
+    "pick",
+    "roll",
+    "jump",
+    "!if",
+    "call",
+    "ret",
+    "abort",
+    "exit",
+    "panic",
+    "ln",
+    "input",
+    "gc",
+    "print",
+    "~float",
+    "~int",
+    "~str",
+    "~bool",
+    "~addr",
+    "%int",
+    "%float",
+    "%str",
+    "%bool",
+    "%drop",
+];
+
+struct Word {
+    address: usize,
+    inputs: Vec<Type>,
+    outputs: Vec<Type>,
+}
+
+enum Block {
+    Word {
+        name: String,
+        outer_stack: Vec<Type>,
+        skip_index: usize,
+    },
+    If {
+        jump_index: usize,
+        stack: Vec<Type>,
+    },
+    Else {
+        skip_index: usize,
+        then_stack: Vec<Type>,
+    },
+    While {
+        start: usize,
+        stack: Vec<Type>,
+    },
+    Do {
+        start: usize,
+        exit_index: usize,
+        stack: Vec<Type>,
+    },
+}
+
+/// Parses the source, `natives` are the words the host provides to the program
+pub fn parse(
+    source: &str,
+    flags: &Flags,
+    natives: &[(String, Signature)],
+) -> Result<PreBinary, Diagnostic> {
+    let tokens = tokenize(source)?;
+    let mut instructions = Vec::new();
+    let mut stack = Vec::new();
+    let mut byte_index = 0;
+    let mut labels = HashMap::new();
+    let mut constants = Vec::new();
+    let mut post_proc = Vec::new();
+    let mut words: HashMap<String, Word> = HashMap::new();
+    let mut blocks = Vec::new();
+    // Names and types of the locals in scope, indexed by their slot in the frame
+    let mut locals: Vec<(String, Type)> = Vec::new();
+    // Amount of locals in scope at the start of every block
+    let mut scopes = Vec::new();
+    // Names and types of the globals, indexed by their slot in the data segment
+    let mut globals: Vec<(String, Type)> = Vec::new();
+    let mut imports: Vec<(String, Signature)> = Vec::new();
+    let mut tokens = tokens.into_iter();
+    let mut spans = Vec::new();
+    let mut last_span = Span::default();
+    while let Some(Token { text: token, span }) = tokens.next() {
+        spans.resize(instructions.len(), last_span);
+        last_span = span;
+        match token.as_str() {
+            name if words.contains_key(name) => {
+                let word = &words[name];
+                byte_index += 10 + 2;
+                instructions.push(Insn::PushAddress(word.address as _));
+                instructions.push(Insn::Call);
+                apply_signature(&mut stack, name, &word.inputs, &word.outputs, span)?;
+            }
+            name if natives.iter().any(|(native, _)| native == name) => {
+                let native = natives.iter().find(|(native, _)| native == name).unwrap();
+                if !native.1.is_supported() {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!(
+                            "Native word '{name}' uses types other than int, float, str and bool"
+                        ),
+                    ));
                 }
-                for ac in chars.by_ref() {
-                    if ac == '\n' {
-                        break;
+                let index = match imports.iter().position(|(import, _)| import == name) {
+                    Some(index) => index,
+                    None => {
+                        imports.push(native.clone());
+                        imports.len() - 1
                     }
+                };
+                let signature = &imports[index].1;
+                byte_index += 10;
+                instructions.push(Insn::Native(index as _));
+                apply_signature(
+                    &mut stack,
+                    name,
+                    &signature.inputs,
+                    &signature.outputs,
+                    span,
+                )?;
+            }
+            name if find_variable(&locals, name).is_some() => {
+                let index = find_variable(&locals, name).unwrap();
+                byte_index += 10;
+                instructions.push(Insn::LoadLocal(index as _));
+                stack.push(locals[index].1);
+            }
+            name if name
+                .strip_suffix('!')
+                .is_some_and(|name| find_variable(&locals, name).is_some()) =>
+            {
+                let index = find_variable(&locals, &name[..name.len() - 1]).unwrap();
+                let (name, type_) = &locals[index];
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if x != *type_ {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Cannot store {x:?} in local '{name}' of type {type_:?}"),
+                    ));
                 }
+                byte_index += 10;
+                instructions.push(Insn::StoreLocal(index as _));
             }
-            '"' => {
-                token.push(c);
-                while let Some(ac) = chars.next() {
-                    if ac == '"' {
-                        token.push(ac);
-                        break;
+            name if find_variable(&globals, name).is_some() => {
+                let index = find_variable(&globals, name).unwrap();
+                byte_index += 10;
+                instructions.push(Insn::LoadGlobal(index as _));
+                stack.push(globals[index].1);
+            }
+            name if name
+                .strip_suffix('!')
+                .is_some_and(|name| find_variable(&globals, name).is_some()) =>
+            {
+                let index = find_variable(&globals, &name[..name.len() - 1]).unwrap();
+                let (name, type_) = &globals[index];
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if x != *type_ {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Cannot store {x:?} in global '{name}' of type {type_:?}"),
+                    ));
+                }
+                byte_index += 10;
+                instructions.push(Insn::StoreGlobal(index as _));
+            }
+            "var" => {
+                if !blocks.is_empty() {
+                    return Err(Diagnostic::new(
+                        span,
+                        "Globals can only be declared at the top level",
+                    ));
+                }
+                let Some(Token {
+                    text: name,
+                    span: name_span,
+                }) = tokens.next()
+                else {
+                    return Err(Diagnostic::new(span, "Expected name of global"));
+                };
+                let Some(name) = name.strip_suffix(':') else {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Expected ':' after name of global {name:?}"),
+                    ));
+                };
+                if !is_variable_name(name) {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Invalid name of global {name:?}"),
+                    ));
+                }
+                if words.contains_key(name)
+                    || natives.iter().any(|(native, _)| native == name)
+                    || find_variable(&globals, name).is_some()
+                {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Global '{name}' is already defined"),
+                    ));
+                }
+                let Some(type_token) = tokens.next() else {
+                    return Err(Diagnostic::new(name_span, "Expected type of global"));
+                };
+                globals.push((name.to_string(), parse_type(&type_token)?));
+            }
+            "let" => {
+                if !matches!(blocks.first(), Some((Block::Word { .. }, _))) {
+                    return Err(Diagnostic::new(
+                        span,
+                        "Locals can only be declared inside words",
+                    ));
+                }
+                let Some(Token {
+                    text: name,
+                    span: name_span,
+                }) = tokens.next()
+                else {
+                    return Err(Diagnostic::new(span, "Expected name of local"));
+                };
+                if !is_variable_name(&name)
+                    || words.contains_key(&name)
+                    || natives.iter().any(|(native, _)| *native == name)
+                    || find_variable(&globals, &name).is_some()
+                {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Invalid name of local {name:?}"),
+                    ));
+                }
+                if locals.len() == MAX_LOCALS {
+                    return Err(Diagnostic::new(name_span, "Too many locals in word"));
+                }
+                expect_stack_length(&stack, 1, span)?;
+                byte_index += 10;
+                instructions.push(Insn::StoreLocal(locals.len() as _));
+                locals.push((name, stack.pop().unwrap()));
+            }
+            "fn" => {
+                if !blocks.is_empty() {
+                    return Err(Diagnostic::new(
+                        span,
+                        "Words can only be defined at the top level",
+                    ));
+                }
+                let Some(Token {
+                    text: name,
+                    span: name_span,
+                }) = tokens.next()
+                else {
+                    return Err(Diagnostic::new(span, "Expected name of word"));
+                };
+                if RESERVED.contains(&name.as_str())
+                    || name.starts_with([':', '@', '&', '"'])
+                    || name.parse::<f64>().is_ok()
+                {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Invalid name of word {name:?}"),
+                    ));
+                }
+                if BUILTINS.contains(&name.as_str()) {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Cannot redefine builtin word '{name}'"),
+                    ));
+                }
+                if words.contains_key(&name)
+                    || natives.iter().any(|(native, _)| *native == name)
+                    || find_variable(&globals, &name).is_some()
+                {
+                    return Err(Diagnostic::new(
+                        name_span,
+                        format!("Word '{name}' is already defined"),
+                    ));
+                }
+                let (inputs, outputs) = parse_signature(&mut tokens, span)?;
+                // Jump over the body of the word
+                let skip_index = instructions.len();
+                byte_index += 10 + 2;
+                instructions.push(Insn::PushAddress(-1));
+                instructions.push(Insn::Jump);
+                let outer_stack = mem::replace(&mut stack, inputs.clone());
+                scopes.push(locals.len());
+                words.insert(
+                    name.clone(),
+                    Word {
+                        address: byte_index,
+                        inputs,
+                        outputs,
+                    },
+                );
+                blocks.push((
+                    Block::Word {
+                        name,
+                        outer_stack,
+                        skip_index,
+                    },
+                    span,
+                ));
+            }
+            "end" => {
+                let Some((block, _)) = blocks.pop() else {
+                    return Err(Diagnostic::new(span, "Unexpected end"));
+                };
+                locals.truncate(scopes.pop().unwrap());
+                match block {
+                    Block::Word {
+                        name,
+                        outer_stack,
+                        skip_index,
+                    } => {
+                        expect_word_outputs(&stack, &name, &words[&name].outputs, span)?;
+                        byte_index += 2;
+                        instructions.push(Insn::Ret);
+                        instructions[skip_index] = Insn::PushAddress(byte_index as _);
+                        stack = outer_stack;
                     }
-                    if ac == '\n' {
-                        return Err(Error::new(ErrorKind::Other, "Invalid string literal"));
+                    Block::If {
+                        jump_index,
+                        stack: if_stack,
+                    } => {
+                        if stack != if_stack {
+                            return Err(Diagnostic::new(
+                                span,
+                                format!(
+                                "If without else changes the stack from {if_stack:?} to {stack:?}"
+                            ),
+                            ));
+                        }
+                        instructions[jump_index] = Insn::PushAddress(byte_index as _);
                     }
-                    if ac == '\\' {
-                        let Some(ac) = chars.next() else {
-                            return Err(Error::new(ErrorKind::Other, "Invalid escape in string"));
-                        };
-                        match ac {
-                            '"' => token.push('"'),
-                            'n' => token.push('\n'),
-                            'r' => token.push('\r'),
-                            't' => token.push('\t'),
-                            '\\' => token.push('\\'),
-                            _ => {
-                                return Err(Error::new(
-                                    ErrorKind::Other,
-                                    "Invalid escape in string",
-                                ));
-                            }
+                    Block::Else {
+                        skip_index,
+                        then_stack,
+                    } => {
+                        if stack != then_stack {
+                            return Err(Diagnostic::new(
+                                span,
+                                format!(
+                                "Branches of if leave different stacks {then_stack:?} and {stack:?}"
+                            ),
+                            ));
                         }
-                        continue;
+                        instructions[skip_index] = Insn::PushAddress(byte_index as _);
                     }
-                    token.push(ac);
-                }
-                tokens.push(token);
-                token = String::new();
-            }
-            _ => {
-                if c.is_whitespace() {
-                    if !token.is_empty() {
-                        tokens.push(token);
-                        token = String::new();
+                    Block::While { .. } => {
+                        return Err(Diagnostic::new(span, "Expected do before end of while"));
                     }
-                    while let Some(ac) = chars.peek() {
-                        if !ac.is_whitespace() {
-                            break;
+                    Block::Do {
+                        start,
+                        exit_index,
+                        stack: loop_stack,
+                    } => {
+                        if stack != loop_stack {
+                            return Err(Diagnostic::new(
+                                span,
+                                format!(
+                                    "Loop body changes the stack from {loop_stack:?} to {stack:?}"
+                                ),
+                            ));
                         }
-                        chars.next().unwrap();
+                        byte_index += 10 + 2;
+                        instructions.push(Insn::PushAddress(start as _));
+                        instructions.push(Insn::Jump);
+                        instructions[exit_index] = Insn::PushAddress(byte_index as _);
                     }
-                    continue;
                 }
-                token.push(c);
-                while let Some(ac) = chars.peek().cloned() {
-                    if ac.is_whitespace() {
-                        break;
-                    }
-                    chars.next().unwrap();
-                    token.push(ac);
+            }
+            "if" if !follows_label_address(&post_proc, &instructions) => {
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                // Only a literal label address makes `if` the plain conditional jump
+                if x.is_code_address() {
+                    return Err(Diagnostic::new(
+                        span,
+                        "Conditional jump with if needs &label directly in front, use !if for computed addresses",
+                    ));
                 }
-                if !token.is_empty() {
-                    tokens.push(token);
-                    token = String::new();
+                if !x.is_bool() {
+                    return Err(Diagnostic::new(span, "Invalid stack for if"));
                 }
+                let jump_index = instructions.len();
+                byte_index += 10 + 2;
+                instructions.push(Insn::PushAddress(-1));
+                instructions.push(Insn::JumpZero);
+                scopes.push(locals.len());
+                blocks.push((
+                    Block::If {
+                        jump_index,
+                        stack: stack.clone(),
+                    },
+                    span,
+                ));
+            }
+            "else" => {
+                let Some((
+                    Block::If {
+                        jump_index,
+                        stack: if_stack,
+                    },
+                    if_span,
+                )) = blocks.pop()
+                else {
+                    return Err(Diagnostic::new(span, "Unexpected else"));
+                };
+                let skip_index = instructions.len();
+                byte_index += 10 + 2;
+                instructions.push(Insn::PushAddress(-1));
+                instructions.push(Insn::Jump);
+                instructions[jump_index] = Insn::PushAddress(byte_index as _);
+                locals.truncate(*scopes.last().unwrap());
+                blocks.push((
+                    Block::Else {
+                        skip_index,
+                        then_stack: mem::replace(&mut stack, if_stack),
+                    },
+                    if_span,
+                ));
+            }
+            "while" => {
+                scopes.push(locals.len());
+                blocks.push((
+                    Block::While {
+                        start: byte_index,
+                        stack: stack.clone(),
+                    },
+                    span,
+                ));
+            }
+            "do" => {
+                let Some((
+                    Block::While {
+                        start,
+                        stack: loop_stack,
+                    },
+                    while_span,
+                )) = blocks.pop()
+                else {
+                    return Err(Diagnostic::new(span, "Unexpected do"));
+                };
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if !x.is_bool() {
+                    return Err(Diagnostic::new(span, "Invalid stack for do"));
+                }
+                if stack != loop_stack {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!(
+                            "Loop condition changes the stack from {loop_stack:?} to {stack:?}"
+                        ),
+                    ));
+                }
+                let exit_index = instructions.len();
+                byte_index += 10 + 2;
+                instructions.push(Insn::PushAddress(-1));
+                instructions.push(Insn::JumpZero);
+                blocks.push((
+                    Block::Do {
+                        start,
+                        exit_index,
+                        stack: loop_stack,
+                    },
+                    while_span,
+                ));
             }
-        }
-    }
-    let mut instructions = Vec::new();
-    let mut stack = Vec::new();
-    let mut byte_index = 0;
-    let mut labels = HashMap::new();
-    let mut constants = Vec::new();
-    let mut post_proc = Vec::new();
-    for token in tokens.into_iter() {
-        match token.as_str() {
             "+" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::AddInt);
                     stack.push(Type::Int);
@@ -171,14 +696,14 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     stack.push(Type::String);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to add"));
+                return Err(Diagnostic::new(span, "Invalid stack to add"));
             }
             "-" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::SubInt);
                     stack.push(Type::Int);
@@ -189,14 +714,14 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     stack.push(Type::Float);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to subtract"));
+                return Err(Diagnostic::new(span, "Invalid stack to subtract"));
             }
             "*" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::MulInt);
                     stack.push(Type::Int);
@@ -207,14 +732,14 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     stack.push(Type::Float);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to multiply"));
+                return Err(Diagnostic::new(span, "Invalid stack to multiply"));
             }
             "/" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::DivInt);
                     stack.push(Type::Int);
@@ -225,126 +750,376 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     stack.push(Type::Float);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to divide"));
+                return Err(Diagnostic::new(span, "Invalid stack to divide"));
+            }
+            "mod" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                expect_equal_type(x, y, span)?;
+                if x.is_int() {
+                    instructions.push(Insn::ModInt);
+                    stack.push(Type::Int);
+                    continue;
+                }
+                if x.is_float() {
+                    instructions.push(Insn::ModFloat);
+                    stack.push(Type::Float);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, "Invalid stack for mod"));
+            }
+            "neg" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if x.is_int() {
+                    instructions.push(Insn::NegInt);
+                    stack.push(Type::Int);
+                    continue;
+                }
+                if x.is_float() {
+                    instructions.push(Insn::NegFloat);
+                    stack.push(Type::Float);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, "Invalid stack to negate"));
+            }
+            "and" | "or" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                expect_equal_type(x, y, span)?;
+                if x.is_int() {
+                    instructions.push(match token.as_str() {
+                        "and" => Insn::AndInt,
+                        _ => Insn::OrInt,
+                    });
+                    stack.push(Type::Int);
+                    continue;
+                }
+                if x.is_bool() {
+                    instructions.push(match token.as_str() {
+                        "and" => Insn::AndBool,
+                        _ => Insn::OrBool,
+                    });
+                    stack.push(Type::Bool);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, format!("Invalid stack for {token}")));
+            }
+            "xor" | "shl" | "shr" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                if !x.is_int() || !y.is_int() {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Invalid stack for {token}, expected two integers"),
+                    ));
+                }
+                instructions.push(match token.as_str() {
+                    "xor" => Insn::XorInt,
+                    "shl" => Insn::ShlInt,
+                    _ => Insn::ShrInt,
+                });
+                stack.push(Type::Int);
+            }
+            "not" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if x.is_int() {
+                    instructions.push(Insn::NotInt);
+                    stack.push(Type::Int);
+                    continue;
+                }
+                if x.is_bool() {
+                    instructions.push(Insn::NotBool);
+                    stack.push(Type::Bool);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, "Invalid stack for not"));
             }
             "=" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::EqInt);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_float() {
                     instructions.push(Insn::EqFloat);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_string() {
                     instructions.push(Insn::EqString);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to compare"));
+                return Err(Diagnostic::new(span, "Invalid stack to compare"));
             }
             "<" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::LtInt);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_float() {
                     instructions.push(Insn::LtFloat);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to compare"));
+                if x.is_string() {
+                    instructions.push(Insn::LtString);
+                    stack.push(Type::Bool);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, "Invalid stack to compare"));
             }
             ">" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::GtInt);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_float() {
                     instructions.push(Insn::GtFloat);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
+                    continue;
+                }
+                if x.is_string() {
+                    instructions.push(Insn::GtString);
+                    stack.push(Type::Bool);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to compare"));
+                return Err(Diagnostic::new(span, "Invalid stack to compare"));
             }
             "<=" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::LeInt);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_float() {
                     instructions.push(Insn::LeFloat);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to compare"));
+                return Err(Diagnostic::new(span, "Invalid stack to compare"));
             }
             ">=" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                expect_equal_type(x, y)?;
+                expect_equal_type(x, y, span)?;
                 if x.is_int() {
                     instructions.push(Insn::GeInt);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
                 if x.is_float() {
                     instructions.push(Insn::GeFloat);
-                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
                     continue;
                 }
-                return Err(Error::new(ErrorKind::Other, "Invalid stack to compare"));
+                return Err(Diagnostic::new(span, "Invalid stack to compare"));
+            }
+            "wrap+" | "wrap-" | "wrap*" | "wrap/" | "sat+" | "sat-" | "sat*" | "sat/" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                if !x.is_int() || !y.is_int() {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Invalid stack for {token}, expected two integers"),
+                    ));
+                }
+                instructions.push(match token.as_str() {
+                    "wrap+" => Insn::AddWrapInt,
+                    "wrap-" => Insn::SubWrapInt,
+                    "wrap*" => Insn::MulWrapInt,
+                    "wrap/" => Insn::DivWrapInt,
+                    "sat+" => Insn::AddSatInt,
+                    "sat-" => Insn::SubSatInt,
+                    "sat*" => Insn::MulSatInt,
+                    _ => Insn::DivSatInt,
+                });
+                stack.push(Type::Int);
+            }
+            "len" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                instructions.push(match x {
+                    Type::String => Insn::LenString,
+                    Type::Array(element) => Insn::LenArray(*element),
+                    _ => return Err(Diagnostic::new(span, "Invalid stack for len")),
+                });
+                stack.push(Type::Int);
+            }
+            "new-array" => {
+                let Some(token) = tokens.next() else {
+                    return Err(Diagnostic::new(span, "Expected type of array elements"));
+                };
+                let element = parse_type(&token)?;
+                let Some(array) = Type::array(element) else {
+                    return Err(Diagnostic::new(
+                        token.span,
+                        format!("Invalid type of array elements {element:?}"),
+                    ));
+                };
+                byte_index += 2;
+                instructions.push(Insn::NewArray(element));
+                stack.push(array);
+            }
+            "get" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                let (Type::Array(element), Type::Int) = (y, x) else {
+                    return Err(Diagnostic::new(span, "Invalid stack for get"));
+                };
+                instructions.push(Insn::GetArray(*element));
+                stack.push(*element);
+            }
+            "set" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 3, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                let z = stack.pop().unwrap();
+                match (z, y) {
+                    (Type::Array(element), Type::Int) if *element == x => {
+                        instructions.push(Insn::SetArray(x));
+                    }
+                    _ => return Err(Diagnostic::new(span, "Invalid stack for set")),
+                }
+            }
+            "push" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                match y {
+                    Type::Array(element) if *element == x => {
+                        instructions.push(Insn::PushArray(x));
+                    }
+                    _ => return Err(Diagnostic::new(span, "Invalid stack for push")),
+                }
+            }
+            "substr" => {
+                byte_index += 2;
+                instructions.push(Insn::SubstrString);
+                expect_stack_length(&stack, 3, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                let z = stack.pop().unwrap();
+                if !x.is_int() || !y.is_int() || !z.is_string() {
+                    return Err(Diagnostic::new(span, "Invalid stack for substr"));
+                }
+                stack.push(Type::String);
+            }
+            "char-at" => {
+                byte_index += 2;
+                instructions.push(Insn::CharAtString);
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                if !x.is_int() || !y.is_string() {
+                    return Err(Diagnostic::new(span, "Invalid stack for char-at"));
+                }
+                stack.push(Type::String);
+            }
+            "find" | "starts-with" | "split-first" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 2, span)?;
+                let x = stack.pop().unwrap();
+                let y = stack.pop().unwrap();
+                if !x.is_string() || !y.is_string() {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Invalid stack for {token}, expected two strings"),
+                    ));
+                }
+                match token.as_str() {
+                    "find" => {
+                        instructions.push(Insn::FindString);
+                        stack.push(Type::Int);
+                    }
+                    "starts-with" => {
+                        instructions.push(Insn::StartsWithString);
+                        stack.push(Type::Bool);
+                    }
+                    _ => {
+                        instructions.push(Insn::SplitFirstString);
+                        stack.extend([Type::String, Type::String, Type::Bool]);
+                    }
+                }
+            }
+            "trim" | "upper" | "lower" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if !x.is_string() {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Invalid stack for {token}, expected string"),
+                    ));
+                }
+                instructions.push(match token.as_str() {
+                    "trim" => Insn::TrimString,
+                    "upper" => Insn::UpperString,
+                    _ => Insn::LowerString,
+                });
+                stack.push(Type::String);
             }
             "drop" => {
                 byte_index += 2;
                 instructions.push(Insn::Drop);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let _ = stack.pop().unwrap();
             }
             "load" => {
                 byte_index += 2;
                 instructions.push(Insn::Load);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
                 if !x.is_int() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        "Invalid stack to load constant",
-                    ));
+                    return Err(Diagnostic::new(span, "Invalid stack to load constant"));
                 }
                 stack.push(Type::String);
             }
             "swap" => {
                 byte_index += 2;
                 instructions.push(Insn::Swap);
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
                 stack.push(x);
@@ -353,7 +1128,7 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             "tRot" => {
                 byte_index += 2;
                 instructions.push(Insn::TriRot);
-                expect_stack_length(&stack, 3)?;
+                expect_stack_length(&stack, 3, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
                 let z = stack.pop().unwrap();
@@ -364,57 +1139,130 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             "dup" => {
                 byte_index += 2;
                 instructions.push(Insn::Dup);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 stack.push(stack[stack.len() - 1]);
             }
             "dDup" => {
                 byte_index += 2;
                 instructions.push(Insn::DiDup);
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 stack.push(stack[stack.len() - 2]);
             }
             "tDup" => {
                 byte_index += 2;
                 instructions.push(Insn::TriDup);
-                expect_stack_length(&stack, 3)?;
+                expect_stack_length(&stack, 3, span)?;
                 stack.push(stack[stack.len() - 3]);
             }
+            "over" => {
+                byte_index += 2;
+                instructions.push(Insn::Over);
+                expect_stack_length(&stack, 2, span)?;
+                stack.push(stack[stack.len() - 2]);
+            }
+            "nip" => {
+                byte_index += 2;
+                instructions.push(Insn::Nip);
+                expect_stack_length(&stack, 2, span)?;
+                stack.remove(stack.len() - 2);
+            }
+            "tuck" => {
+                byte_index += 2;
+                instructions.push(Insn::Tuck);
+                expect_stack_length(&stack, 2, span)?;
+                stack.insert(stack.len() - 2, stack[stack.len() - 1]);
+            }
+            "2dup" => {
+                byte_index += 2;
+                instructions.push(Insn::DupPair);
+                expect_stack_length(&stack, 2, span)?;
+                stack.extend_from_within(stack.len() - 2..);
+            }
+            "2drop" => {
+                byte_index += 2;
+                instructions.push(Insn::DropPair);
+                expect_stack_length(&stack, 2, span)?;
+                stack.truncate(stack.len() - 2);
+            }
+            "2swap" => {
+                byte_index += 2;
+                instructions.push(Insn::SwapPair);
+                expect_stack_length(&stack, 4, span)?;
+                let len = stack.len();
+                stack[len - 4..].rotate_left(2);
+            }
+            "pick" | "roll" => {
+                let Some(Token {
+                    text,
+                    span: depth_span,
+                }) = tokens.next()
+                else {
+                    return Err(Diagnostic::new(
+                        span,
+                        format!("Expected depth after {token}"),
+                    ));
+                };
+                let Ok(depth) = text.parse::<usize>() else {
+                    return Err(Diagnostic::new(
+                        depth_span,
+                        format!("Invalid depth for {token}"),
+                    ));
+                };
+                byte_index += 10;
+                expect_stack_length(&stack, depth.saturating_add(1), span)?;
+                let index = stack.len() - 1 - depth;
+                if token == "pick" {
+                    instructions.push(Insn::Pick(depth as _));
+                    stack.push(stack[index]);
+                } else {
+                    instructions.push(Insn::Roll(depth as _));
+                    let x = stack.remove(index);
+                    stack.push(x);
+                }
+            }
             "jump" => {
                 byte_index += 2;
                 instructions.push(Insn::Jump);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let addr = stack.pop().unwrap();
-                if !addr.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack to jump"));
+                if !addr.is_code_address() {
+                    return Err(Diagnostic::new(span, "Invalid stack to jump"));
                 }
             }
             "if" => {
                 byte_index += 2;
                 instructions.push(Insn::JumpNotZero);
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                if !x.is_int() || !y.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack for if"));
+                if !x.is_code_address() || !y.is_bool() {
+                    return Err(Diagnostic::new(span, "Invalid stack for if"));
                 }
             }
             "!if" => {
                 byte_index += 2;
                 instructions.push(Insn::JumpZero);
-                expect_stack_length(&stack, 2)?;
+                expect_stack_length(&stack, 2, span)?;
                 let x = stack.pop().unwrap();
                 let y = stack.pop().unwrap();
-                if !x.is_int() || !y.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack for !if"));
+                if !x.is_code_address() || !y.is_bool() {
+                    return Err(Diagnostic::new(span, "Invalid stack for !if"));
                 }
             }
             "call" => {
                 byte_index += 2;
                 instructions.push(Insn::Call);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let addr = stack.pop().unwrap();
-                if !addr.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack for call"));
+                if !addr.is_code_address() {
+                    return Err(Diagnostic::new(span, "Invalid stack for call"));
+                }
+            }
+            "ret" => {
+                byte_index += 2;
+                instructions.push(Insn::Ret);
+                if let Some((Block::Word { name, .. }, _)) = blocks.first() {
+                    expect_word_outputs(&stack, name, &words[name].outputs, span)?;
                 }
             }
             "abort" => {
@@ -424,19 +1272,19 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             "exit" => {
                 byte_index += 2;
                 instructions.push(Insn::Exit);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
                 if !x.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack"));
+                    return Err(Diagnostic::new(span, "Invalid stack"));
                 }
             }
             "panic" => {
                 byte_index += 2;
                 instructions.push(Insn::Panic);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
                 if !x.is_string() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack"));
+                    return Err(Diagnostic::new(span, "Invalid stack"));
                 }
             }
             "ln" => {
@@ -454,39 +1302,101 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             }
             "print" => {
                 byte_index += 2;
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
                 instructions.push(match x {
                     Type::Int => Insn::PrintInt,
                     Type::Float => Insn::PrintFloat,
                     Type::String => Insn::PrintString,
-                    Type::CodeAddress => Insn::PrintInt,
+                    Type::CodeAddress => {
+                        return Err(Diagnostic::new(
+                            span,
+                            "Cannot print code address, convert it with ~int first",
+                        ))
+                    }
+                    Type::Bool => Insn::PrintBool,
+                    Type::Array(_) => return Err(Diagnostic::new(span, "Cannot print array")),
                 });
             }
             "~float" => {
                 byte_index += 2;
-                instructions.push(Insn::NumConvFloat);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
-                if !x.is_int() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack"));
+                if x.is_int() {
+                    instructions.push(Insn::NumConvFloat);
+                    stack.push(Type::Float);
+                    continue;
                 }
-                stack.push(Type::Float);
+                if x.is_string() {
+                    instructions.push(Insn::StrConvFloat);
+                    stack.push(Type::Float);
+                    stack.push(Type::Bool);
+                    continue;
+                }
+                return Err(Diagnostic::new(span, "Invalid stack"));
             }
             "~int" => {
                 byte_index += 2;
-                instructions.push(Insn::NumConvInt);
-                expect_stack_length(&stack, 1)?;
+                expect_stack_length(&stack, 1, span)?;
                 let x = stack.pop().unwrap();
-                if !x.is_float() {
-                    return Err(Error::new(ErrorKind::Other, "Invalid stack"));
+                if x.is_float() {
+                    instructions.push(Insn::NumConvInt);
+                } else if x.is_bool() {
+                    instructions.push(Insn::BoolConvInt);
+                } else if x.is_code_address() {
+                    instructions.push(Insn::AddrConvInt);
+                } else if x.is_string() {
+                    instructions.push(Insn::StrConvInt);
+                    stack.push(Type::Int);
+                    stack.push(Type::Bool);
+                    continue;
+                } else {
+                    return Err(Diagnostic::new(span, "Invalid stack"));
                 }
                 stack.push(Type::Int);
             }
+            "~str" => {
+                byte_index += 2;
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if x.is_int() {
+                    instructions.push(Insn::NumConvString);
+                } else if x.is_float() {
+                    instructions.push(Insn::FloatConvString);
+                } else {
+                    return Err(Diagnostic::new(span, "Invalid stack"));
+                }
+                stack.push(Type::String);
+            }
+            "~bool" => {
+                byte_index += 2;
+                instructions.push(Insn::NumConvBool);
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if !x.is_int() {
+                    return Err(Diagnostic::new(span, "Invalid stack"));
+                }
+                stack.push(Type::Bool);
+            }
+            "~addr" => {
+                byte_index += 2;
+                instructions.push(Insn::NumConvAddress);
+                expect_stack_length(&stack, 1, span)?;
+                let x = stack.pop().unwrap();
+                if !x.is_int() {
+                    return Err(Diagnostic::new(span, "Invalid stack"));
+                }
+                stack.push(Type::CodeAddress);
+            }
+            "true" | "false" => {
+                byte_index += 10;
+                instructions.push(Insn::PushBool(token == "true"));
+                stack.push(Type::Bool);
+            }
             "%int" => {
                 if flags.verify {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Diagnostic::new(
+                        span,
                         "Feature only available in noverify mode",
                     ));
                 }
@@ -494,8 +1404,8 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             }
             "%float" => {
                 if flags.verify {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Diagnostic::new(
+                        span,
                         "Feature only available in noverify mode",
                     ));
                 }
@@ -503,25 +1413,31 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
             }
             "%str" => {
                 if flags.verify {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Diagnostic::new(
+                        span,
                         "Feature only available in noverify mode",
                     ));
                 }
                 stack.push(Type::String);
             }
+            "%bool" => {
+                if flags.verify {
+                    return Err(Diagnostic::new(
+                        span,
+                        "Feature only available in noverify mode",
+                    ));
+                }
+                stack.push(Type::Bool);
+            }
             "%drop" => {
                 if flags.verify {
-                    return Err(Error::new(
-                        ErrorKind::Other,
+                    return Err(Diagnostic::new(
+                        span,
                         "Feature only available in noverify mode",
                     ));
                 }
                 if stack.is_empty() {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        "Cannot pop from empty type stack",
-                    ));
+                    return Err(Diagnostic::new(span, "Cannot pop from empty type stack"));
                 }
                 stack.pop().unwrap();
             }
@@ -534,9 +1450,10 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     post_proc.push(PostProc::InsertLabelAddress {
                         index: instructions.len(),
                         label: label.to_string(),
+                        span,
                     });
                     byte_index += 10 + 2;
-                    instructions.push(Insn::PushInt(-1));
+                    instructions.push(Insn::PushAddress(-1));
                     instructions.push(Insn::Jump);
                     continue;
                 }
@@ -544,10 +1461,11 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     post_proc.push(PostProc::InsertLabelAddress {
                         index: instructions.len(),
                         label: label.to_string(),
+                        span,
                     });
                     byte_index += 10;
-                    instructions.push(Insn::PushInt(-1));
-                    stack.push(Type::Int);
+                    instructions.push(Insn::PushAddress(-1));
+                    stack.push(Type::CodeAddress);
                     continue;
                 }
                 if let Some(string) = token.strip_prefix('"') {
@@ -572,46 +1490,273 @@ pub fn parse(source: &str, flags: &Flags) -> Result<PreBinary> {
                     stack.push(Type::Int);
                     continue;
                 }
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Unknown token {token:?}"),
-                ));
+                return Err(Diagnostic::new(span, format!("Unknown token {token:?}")));
             }
         }
     }
+    match blocks.last() {
+        Some((Block::Word { name, .. }, span)) => {
+            return Err(Diagnostic::new(
+                *span,
+                format!("Missing end of word definition '{name}'"),
+            ));
+        }
+        Some((Block::If { .. } | Block::Else { .. }, span)) => {
+            return Err(Diagnostic::new(*span, "Missing end of if"));
+        }
+        Some((Block::While { .. } | Block::Do { .. }, span)) => {
+            return Err(Diagnostic::new(*span, "Missing end of while"));
+        }
+        None => {}
+    }
     for proc in post_proc {
         match proc {
-            PostProc::InsertLabelAddress { index, label } => {
+            PostProc::InsertLabelAddress { index, label, span } => {
                 let Some(constant_index) = labels.get(&label) else {
-                    return Err(Error::new(
-                        ErrorKind::Other,
-                        format!("Unknown label '{label}'"),
-                    ));
+                    return Err(Diagnostic::new(span, format!("Unknown label '{label}'")));
                 };
-                instructions[index] = Insn::PushInt(*constant_index as _);
+                instructions[index] = Insn::PushAddress(*constant_index as _);
             }
         }
     }
+    spans.resize(instructions.len(), last_span);
+    let mut symbols: Vec<_> = words
+        .into_iter()
+        .map(|(name, word)| (name, word.address))
+        .collect();
+    symbols.sort_by_key(|(_, offset)| *offset);
+    let mut labels: Vec<_> = labels.into_iter().chain(symbols.clone()).collect();
+    labels.sort_by_key(|(_, offset)| *offset);
     Ok(PreBinary {
         constants,
+        globals: globals.into_iter().map(|(_, type_)| type_).collect(),
         instructions,
+        spans,
+        labels,
+        symbols,
+        imports,
     })
 }
 
-fn expect_equal_type(x: Type, y: Type) -> Result<()> {
+fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut source = Source {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    while let Some(&c) = source.chars.peek() {
+        if c.is_whitespace() {
+            source.next();
+            continue;
+        }
+        if c == '#' {
+            while source.next().is_some_and(|c| c != '\n') {}
+            continue;
+        }
+        let mut span = Span {
+            line: source.line,
+            column: source.column,
+            len: 0,
+        };
+        let mut text = String::new();
+        if c == '"' {
+            text.push(source.next().unwrap());
+            loop {
+                let escape = source.span(2);
+                match source.next() {
+                    Some('"') => {
+                        text.push('"');
+                        break;
+                    }
+                    None | Some('\n') => {
+                        span.len = 1;
+                        return Err(Diagnostic::new(span, "Invalid string literal"));
+                    }
+                    Some('\\') => match source.next() {
+                        Some('"') => text.push('"'),
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some('t') => text.push('\t'),
+                        Some('\\') => text.push('\\'),
+                        _ => return Err(Diagnostic::new(escape, "Invalid escape in string")),
+                    },
+                    Some(c) => text.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = source.chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                text.push(source.next().unwrap());
+            }
+        }
+        span.len = source.column - span.column;
+        tokens.push(Token { text, span });
+    }
+    Ok(tokens)
+}
+
+impl Source<'_> {
+    fn span(&self, len: usize) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            len,
+        }
+    }
+}
+
+impl Iterator for Source<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            }
+            '\r' => {}
+            _ => self.column += 1,
+        }
+        Some(c)
+    }
+}
+
+/// Checks whether the last token was a label address, which makes `if` a plain conditional jump
+fn follows_label_address(post_proc: &[PostProc], instructions: &[Insn]) -> bool {
+    matches!(post_proc.last(), Some(PostProc::InsertLabelAddress { index, .. }) if *index + 1 == instructions.len())
+}
+
+fn parse_signature(
+    tokens: &mut impl Iterator<Item = Token>,
+    span: Span,
+) -> Result<(Vec<Type>, Vec<Type>), Diagnostic> {
+    match tokens.next() {
+        Some(token) if token.text == "(" => {}
+        Some(token) => {
+            return Err(Diagnostic::new(
+                token.span,
+                "Expected stack effect after name of word",
+            ))
+        }
+        None => {
+            return Err(Diagnostic::new(
+                span,
+                "Expected stack effect after name of word",
+            ))
+        }
+    }
+    let mut inputs = Vec::new();
+    loop {
+        match tokens.next() {
+            Some(token) if token.text == "--" => break,
+            Some(token) => inputs.push(parse_type(&token)?),
+            None => return Err(Diagnostic::new(span, "Unterminated stack effect")),
+        }
+    }
+    let mut outputs = Vec::new();
+    loop {
+        match tokens.next() {
+            Some(token) if token.text == ")" => break,
+            Some(token) => outputs.push(parse_type(&token)?),
+            None => return Err(Diagnostic::new(span, "Unterminated stack effect")),
+        }
+    }
+    Ok((inputs, outputs))
+}
+
+/// Parses the name of a type, arrays are written as the type of their elements in brackets
+fn parse_type(token: &Token) -> Result<Type, Diagnostic> {
+    let type_ = match token
+        .text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        Some(element) => type_name(element).and_then(Type::array),
+        None => type_name(&token.text),
+    };
+    type_.ok_or_else(|| Diagnostic::new(token.span, format!("Unknown type {:?}", token.text)))
+}
+
+fn type_name(text: &str) -> Option<Type> {
+    match text {
+        "int" => Some(Type::Int),
+        "float" => Some(Type::Float),
+        "str" => Some(Type::String),
+        "bool" => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+/// Replaces the inputs of a call on the type stack with its outputs
+fn apply_signature(
+    stack: &mut Vec<Type>,
+    name: &str,
+    inputs: &[Type],
+    outputs: &[Type],
+    span: Span,
+) -> Result<(), Diagnostic> {
+    expect_stack_length(stack, inputs.len(), span)?;
+    let args = stack.split_off(stack.len() - inputs.len());
+    if args != inputs {
+        return Err(Diagnostic::new(
+            span,
+            format!("Word '{name}' expects {inputs:?} on the stack but found {args:?}"),
+        ));
+    }
+    stack.extend_from_slice(outputs);
+    Ok(())
+}
+
+fn expect_word_outputs(
+    stack: &[Type],
+    name: &str,
+    outputs: &[Type],
+    span: Span,
+) -> Result<(), Diagnostic> {
+    if stack != outputs {
+        return Err(Diagnostic::new(
+            span,
+            format!(
+                "Word '{name}' declares {outputs:?} as result but leaves {stack:?} on the stack"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the slot of the last declared variable with the name
+fn find_variable(variables: &[(String, Type)], name: &str) -> Option<usize> {
+    variables.iter().rposition(|(variable, _)| variable == name)
+}
+
+/// Returns whether the name can be declared as a local or a global
+fn is_variable_name(name: &str) -> bool {
+    !(name.is_empty()
+        || RESERVED.contains(&name)
+        || BUILTINS.contains(&name)
+        || name.starts_with([':', '@', '&', '"'])
+        || name.ends_with('!')
+        || name.parse::<f64>().is_ok())
+}
+
+fn expect_equal_type(x: Type, y: Type, span: Span) -> Result<(), Diagnostic> {
     if x != y {
-        return Err(Error::new(
-            ErrorKind::Other,
+        return Err(Diagnostic::new(
+            span,
             format!("Expected equal types on stack but found {x:?} and {y:?}"),
         ));
     }
     Ok(())
 }
 
-fn expect_stack_length(stack: &[Type], len: usize) -> Result<()> {
+fn expect_stack_length(stack: &[Type], len: usize, span: Span) -> Result<(), Diagnostic> {
     if stack.len() < len {
-        return Err(Error::new(
-            ErrorKind::Other,
+        return Err(Diagnostic::new(
+            span,
             format!(
                 "Expected stack with minimum length of {len}, but got length {}",
                 stack.len()
@@ -1,88 +1,291 @@
 use std::{
     alloc::{alloc_zeroed, dealloc, Layout},
-    io::{stdin, stdout, Write},
+    fmt::{self, Display, Formatter},
+    io::{self, stdin, stdout, Write},
     mem::size_of,
 };
 
-use crate::{bytecode::*, Flags};
+use crate::{
+    bytecode::*,
+    debug::DebugInfo,
+    heap::{GcStats, Handle, Heap, OBJECT_OVERHEAD},
+    Flags,
+};
+
+/// Function of the host that can be called like a word
+pub type NativeFunction<'a> = Box<dyn FnMut(&mut NativeStack<'_, 'a>) -> Result<(), String> + 'a>;
 
 const ALIGNMENT: usize = 4096;
+/// Size of the stack in bytes if its maximum size is not known
+const DEFAULT_STACK_SIZE: usize = 4096 * 16;
+/// Maximum amount of nested calls
+const MAX_CALL_DEPTH: usize = 1 << 16;
+/// Maximum amount of locals of all active subroutines
+const MAX_TOTAL_LOCALS: usize = 1 << 20;
 
 #[derive(Clone, Copy)]
 pub union Value {
     pub int: i64,
     pub float: f64,
-    pub string: *const String,
+    pub string: Handle,
+    pub array: Handle,
 }
 
+/// The virtual machine running a program.
+///
+/// Fields that accesses without checks rely on are private, so safe code can not break them.
 pub struct Runtime<'a> {
-    pub bp: *mut Value,
-    pub sp: *mut Value,
-    pub pc: usize,
-    pub code: &'a [u8],
-    pub stack_size: usize,
-    pub layout: Layout,
-    pub constants: Vec<String>,
-    pub string_pool: Vec<String>,
-    pub string_pool_marks: Vec<u8>,
-    pub panic_handler: fn(PanicInfo) -> !,
-    pub flags: Flags,
+    pub(crate) bp: *mut Value,
+    pub(crate) sp: *mut Value,
+    pub(crate) pc: usize,
+    /// Offset of the instruction being executed, which is where faults happen
+    insn_start: usize,
+    code: &'a [u8],
+    pub(crate) stack_size: usize,
+    /// Whether accesses to the stack and the code are checked, which is not needed if the verifier
+    /// proved their bounds
+    checked: bool,
+    layout: Layout,
+    pub(crate) heap: Heap,
+    /// Natives are called with the amount of values on the stack their import declares
+    imports: Vec<(String, Signature)>,
+    natives: Vec<Native<'a>>,
+    debug_info: Option<DebugInfo>,
+    instruction_starts: Vec<bool>,
+    /// Return addresses with the frames of the callers
+    return_stack: Vec<(usize, usize)>,
+    /// Locals of all active subroutines
+    locals: Vec<Value>,
+    /// Index of the first local of the current subroutine
+    frame: usize,
+    globals: Vec<Value>,
+    /// The layout of the heap and the jump checks are fixed once the runtime exists
+    flags: Flags,
 }
 
 //#[allow(unused)]
 impl<'a> Runtime<'a> {
-    pub fn new(
+    /// Creates a runtime with room for the maximum amount of values on the stack, if it is not
+    /// given every access to the stack and the code is checked. Globals start as zero, false or
+    /// empty.
+    ///
+    /// Fails if the globals do not fit into the maximum size of the heap.
+    ///
+    /// The maximum stack size must only be given if the verifier proved it for the code, which is
+    /// why embedders create runtimes through [`crate::Program::runtime`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
         code: &'a [u8],
         pc: usize,
-        stack_size: usize,
-        panic_handler: fn(PanicInfo) -> !,
+        max_stack_size: Option<usize>,
         constants: Vec<String>,
+        globals: &[Type],
+        imports: Vec<(String, Signature)>,
+        debug_info: Option<DebugInfo>,
         flags: Flags,
-    ) -> Self {
+    ) -> Result<Self, VmError> {
+        let mut heap = Heap::new(constants, flags.heap);
+        let globals = globals
+            .iter()
+            .map(|type_| initial_value(&mut heap, *type_))
+            .collect::<Result<_, _>>()?;
+        let stack_size = max_stack_size.map_or(DEFAULT_STACK_SIZE, |size| {
+            (size * size_of::<Value>()).max(ALIGNMENT)
+        });
         let layout = unsafe { Layout::from_size_align_unchecked(stack_size, ALIGNMENT) };
         let bp = unsafe { alloc_zeroed(layout) };
-        Self {
+        Ok(Self {
             bp: bp as _,
             sp: bp as _,
             stack_size,
+            checked: max_stack_size.is_none(),
             layout,
-            panic_handler,
             pc,
-            constants,
-            string_pool: Vec::with_capacity(0),
-            string_pool_marks: Vec::with_capacity(0),
+            insn_start: pc,
+            heap,
+            imports,
+            natives: Vec::new(),
+            debug_info,
+            instruction_starts: if flags.checked_jumps {
+                instruction_starts(code)
+            } else {
+                Vec::with_capacity(0)
+            },
+            return_stack: Vec::new(),
+            locals: Vec::new(),
+            frame: 0,
+            globals,
             code,
             flags,
+        })
+    }
+
+    /// Natives the program imports, with the signatures they are called with
+    pub fn imports(&self) -> &[(String, Signature)] {
+        &self.imports
+    }
+
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap.size()
+    }
+
+    /// Offset of the next instruction in the code
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Amount of values on the stack
+    pub fn stack_len(&self) -> usize {
+        self.len()
+    }
+
+    /// Size of the stack in bytes
+    pub fn stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    /// Location in the source of the instruction that stopped the runtime, if the program has
+    /// debug information
+    pub fn location(&self) -> Option<String> {
+        self.debug_info
+            .as_ref()
+            .and_then(|debug_info| debug_info.location(self.insn_start as _))
+    }
+
+    /// Makes the function callable by programs importing a native word with the same name and
+    /// signature, a previously registered function with the name is replaced
+    pub fn register(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        function: impl FnMut(&mut NativeStack<'_, 'a>) -> Result<(), String> + 'a,
+    ) {
+        let native = Native {
+            name: name.to_string(),
+            signature,
+            function: Some(Box::new(function)),
+        };
+        match self.natives.iter_mut().find(|native| native.name == name) {
+            Some(existing) => *existing = native,
+            None => self.natives.push(native),
+        }
+    }
+
+    /// Resolves the imports of the program to the registered natives
+    fn link(&self) -> Result<Vec<usize>, VmError> {
+        let mut linked = Vec::with_capacity(self.imports.len());
+        for (name, signature) in &self.imports {
+            let Some(index) = self.natives.iter().position(|native| native.name == *name) else {
+                return Err(VmError::UnknownNative { name: name.clone() });
+            };
+            if self.natives[index].signature != *signature || !signature.is_supported() {
+                return Err(VmError::NativeSignatureMismatch { name: name.clone() });
+            }
+            linked.push(index);
         }
+        Ok(linked)
     }
 
-    fn push(&mut self, value: Value) {
+    fn call_native(&mut self, index: usize) -> Result<(), VmError> {
+        let native = &mut self.natives[index];
+        let mut function = native.function.take().unwrap();
+        let signature = native.signature.clone();
+        let mut stack = NativeStack {
+            vm: self,
+            inputs: signature.inputs.clone(),
+            outputs: Vec::new(),
+        };
+        let mut result = function(&mut stack);
+        if result.is_ok() && !stack.inputs.is_empty() {
+            result = Err(format!("Left arguments {:?} on the stack", stack.inputs));
+        }
+        if result.is_ok() && stack.outputs != signature.outputs {
+            result = Err(format!(
+                "Pushed {:?} but declares {:?} as result",
+                stack.outputs, signature.outputs
+            ));
+        }
+        let native = &mut self.natives[index];
+        native.function = Some(function);
+        result.map_err(|message| VmError::Native {
+            name: native.name.clone(),
+            message,
+        })
+    }
+
+    /// Amount of values on the stack
+    fn len(&self) -> usize {
+        unsafe { self.sp.offset_from(self.bp) as usize }
+    }
+
+    /// Checks that there are at least `amount` values on the stack
+    fn require<const CHECKED: bool>(&self, amount: usize) -> Result<(), VmError> {
+        if CHECKED && self.len() < amount {
+            return Err(VmError::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    fn push<const CHECKED: bool>(&mut self, value: Value) -> Result<(), VmError> {
+        if CHECKED && self.len() == self.stack_size / size_of::<Value>() {
+            return Err(VmError::StackOverflow);
+        }
         unsafe {
             *self.sp = value;
             self.sp = self.sp.add(1);
         }
+        Ok(())
     }
 
-    fn pop(&mut self) -> Value {
+    fn pop<const CHECKED: bool>(&mut self) -> Result<Value, VmError> {
+        self.require::<CHECKED>(1)?;
         unsafe {
             self.sp = self.sp.sub(1);
-            *self.sp
+            Ok(*self.sp)
         }
     }
 
-    fn alloc_string(&mut self, value: String) -> Value {
-        for i in 0..self.string_pool_marks.len() {
-            if self.string_pool_marks[i] == 0 {
-                self.string_pool[i] = value;
-                return Value {
-                    string: &self.string_pool[i],
-                };
-            }
+    /// Replaces the top two integers on the stack with the result of the operation, which gets
+    /// the second integer first
+    fn int_op<const CHECKED: bool>(
+        &mut self,
+        op: impl FnOnce(i64, i64) -> Result<i64, VmError>,
+    ) -> Result<(), VmError> {
+        let x = unsafe { self.pop::<CHECKED>()?.int };
+        let y = unsafe { self.pop::<CHECKED>()?.int };
+        self.push::<CHECKED>(Value { int: op(y, x)? })
+    }
+
+    /// Stores the string on the heap, collecting garbage first if the heap grew too large
+    fn alloc_string(&mut self, value: String) -> Result<Value, VmError> {
+        let size = OBJECT_OVERHEAD + value.len();
+        if self.heap.needs_collection(size) {
+            self.collect_garbage();
+        }
+        match self.heap.alloc(value) {
+            Some(handle) => Ok(Value { string: handle }),
+            None => Err(VmError::OutOfMemory { size }),
+        }
+    }
+
+    /// Creates an empty array on the heap, collecting garbage first if the heap grew too large
+    fn alloc_array(&mut self, element: Type) -> Result<Value, VmError> {
+        if self.heap.needs_collection(OBJECT_OVERHEAD) {
+            self.collect_garbage();
         }
-        self.string_pool_marks.push(1);
-        self.string_pool.push(value);
-        Value {
-            string: self.string_pool.last().unwrap(),
+        match self.heap.alloc_array(element) {
+            Some(handle) => Ok(Value { array: handle }),
+            None => Err(VmError::OutOfMemory {
+                size: OBJECT_OVERHEAD,
+            }),
         }
     }
 
@@ -90,68 +293,142 @@ impl<'a> Runtime<'a> {
         self.pc >= self.code.len()
     }
 
-    fn fetch_insn(&mut self) -> u16 {
+    fn fetch_insn<const CHECKED: bool>(&mut self) -> Result<u16, VmError> {
+        if CHECKED && self.pc + 2 > self.code.len() {
+            return Err(VmError::TruncatedInstruction);
+        }
         let insn = unsafe { (self.code.as_ptr().add(self.pc) as *const u16).read_unaligned() };
         self.pc += 2;
-        insn
+        Ok(insn)
     }
 
-    fn fetch_const(&mut self) -> Value {
+    fn fetch_const<const CHECKED: bool>(&mut self) -> Result<Value, VmError> {
+        if CHECKED && self.pc + 8 > self.code.len() {
+            return Err(VmError::TruncatedInstruction);
+        }
         let insn = unsafe { (self.code.as_ptr().add(self.pc) as *const Value).read_unaligned() };
         self.pc += 8;
-        insn
+        Ok(insn)
     }
 
+    fn jump(&mut self, target: i64) -> Result<(), VmError> {
+        if self.flags.checked_jumps
+            && (target < 0
+                || !self
+                    .instruction_starts
+                    .get(target as usize)
+                    .unwrap_or(&false))
+        {
+            return Err(VmError::InvalidJump { target });
+        }
+        self.pc = target as _;
+        Ok(())
+    }
+
+    /// Frees all objects that are not reachable from the stack, the locals or the globals, every
+    /// value is treated as a possible handle
     fn collect_garbage(&mut self) {
-        unsafe {
-            let mut gp = self.bp;
-            let spsp = self.string_pool.as_mut_ptr();
-            let spep = self.string_pool.as_mut_ptr().add(self.string_pool.len());
-            for i in 0..self.string_pool_marks.len() {
-                self.string_pool_marks[i] = 0;
-            }
-            while gp != self.sp {
-                let entry = (*gp).string;
-                if entry >= spsp && entry < spep {
-                    let offset = entry.sub(spsp as _) as usize / size_of::<String>();
-                    self.string_pool_marks[offset] = 1;
-                }
-                gp = gp.add(1);
-            }
-            for i in 0..self.string_pool_marks.len() {
-                if self.string_pool_marks[i] == 0 {
-                    self.string_pool[i] = String::with_capacity(0);
-                }
-            }
+        let stack = unsafe { std::slice::from_raw_parts(self.bp, self.len()) };
+        self.heap.collect(
+            stack
+                .iter()
+                .chain(&self.locals)
+                .chain(&self.globals)
+                .map(|value| unsafe { value.string }),
+        );
+    }
+
+    fn global(&mut self, index: i64) -> Result<&mut Value, VmError> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.globals.get_mut(index))
+            .ok_or(VmError::InvalidGlobal { index })
+    }
+
+    /// Returns the index of the local in the current frame, which is allocated if needed
+    fn local(&mut self, index: i64) -> Result<usize, VmError> {
+        let Some(index) = usize::try_from(index)
+            .ok()
+            .filter(|&index| index < MAX_LOCALS)
+        else {
+            return Err(VmError::InvalidLocal { index });
+        };
+        let index = self.frame + index;
+        if index >= MAX_TOTAL_LOCALS {
+            return Err(VmError::StackOverflow);
+        }
+        if index >= self.locals.len() {
+            self.locals.resize(index + 1, Value { int: 0 });
+        }
+        Ok(index)
+    }
+
+    fn array(&self, value: Value) -> Result<&[Value], VmError> {
+        let handle = unsafe { value.array };
+        self.heap
+            .array(handle)
+            .ok_or(VmError::InvalidArray { handle })
+    }
+
+    /// Returns the element of the array at the index, which has to be in bounds
+    fn element(&mut self, array: Value, index: i64) -> Result<&mut Value, VmError> {
+        let handle = unsafe { array.array };
+        let Some(elements) = self.heap.array_mut(handle) else {
+            return Err(VmError::InvalidArray { handle });
+        };
+        let len = elements.len();
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| elements.get_mut(index))
+            .ok_or(VmError::IndexOutOfBounds { index, len })
+    }
+
+    fn string(&self, value: Value) -> Result<&String, VmError> {
+        let handle = unsafe { value.string };
+        self.heap
+            .get(handle)
+            .ok_or(VmError::InvalidString { handle })
+    }
+
+    /// Runs the program until it ends, running past the end of the code exits with code 0
+    pub fn execute(&mut self) -> Result<ExitStatus, VmError> {
+        let linked = self.link()?;
+        if self.checked {
+            self.run::<true>(&linked)
+        } else {
+            self.run::<false>(&linked)
         }
     }
 
-    pub fn execute(&mut self) {
+    /// Accesses to the stack and the code are only checked if `CHECKED` is set
+    fn run<const CHECKED: bool>(&mut self, linked: &[usize]) -> Result<ExitStatus, VmError> {
         let mut stdout = stdout();
         let stdin = stdin();
         unsafe {
             while !self.is_at_end() {
-                let insn = self.fetch_insn();
+                self.insn_start = self.pc;
+                let insn = self.fetch_insn::<CHECKED>()?;
                 match insn {
                     INSN_DROP => {
-                        self.sp = self.sp.sub(1);
+                        self.pop::<CHECKED>()?;
                     }
                     INSN_LOAD => {
-                        let i = self.pop().int;
-                        if i < 0 {
-                            (self.panic_handler)(PanicInfo::InvalidConstant { vm: self, index: i });
-                        }
-                        let Some(constant) = self.constants.get(i as usize) else {
-                            (self.panic_handler)(PanicInfo::InvalidConstant { vm: self, index: i });
+                        let i = self.pop::<CHECKED>()?.int;
+                        let Some(constant) =
+                            usize::try_from(i).ok().and_then(|i| self.heap.constant(i))
+                        else {
+                            return Err(VmError::InvalidConstant { index: i });
                         };
-                        self.push(Value { string: constant });
+                        self.push::<CHECKED>(Value { string: constant })?;
                     }
                     INSN_SWAP => {
+                        self.require::<CHECKED>(2)?;
                         let tmp = *self.sp.sub(2);
                         *self.sp.sub(2) = *self.sp.sub(1);
                         *self.sp.sub(1) = tmp;
                     }
                     INSN_TROT => {
+                        self.require::<CHECKED>(3)?;
                         let tmp_x = *self.sp.sub(1);
                         let tmp_y = *self.sp.sub(2);
                         *self.sp.sub(1) = *self.sp.sub(3);
@@ -159,230 +436,586 @@ impl<'a> Runtime<'a> {
                         *self.sp.sub(2) = tmp_x;
                     }
                     INSN_DUP => {
-                        *self.sp = *self.sp.sub(1);
-                        self.sp = self.sp.add(1);
+                        self.require::<CHECKED>(1)?;
+                        let value = *self.sp.sub(1);
+                        self.push::<CHECKED>(value)?;
                     }
                     INSN_DDUP => {
-                        *self.sp = *self.sp.sub(2);
-                        self.sp = self.sp.add(1);
+                        self.require::<CHECKED>(2)?;
+                        let value = *self.sp.sub(2);
+                        self.push::<CHECKED>(value)?;
                     }
                     INSN_TDUP => {
-                        *self.sp = *self.sp.sub(3);
-                        self.sp = self.sp.add(1);
+                        self.require::<CHECKED>(3)?;
+                        let value = *self.sp.sub(3);
+                        self.push::<CHECKED>(value)?;
                     }
-                    INSN_PUSH_I64 | INSN_PUSH_F64 => {
-                        *self.sp = self.fetch_const();
-                        self.sp = self.sp.add(1);
+                    INSN_OVER => {
+                        self.require::<CHECKED>(2)?;
+                        let value = *self.sp.sub(2);
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_NIP => {
+                        self.require::<CHECKED>(2)?;
+                        *self.sp.sub(2) = *self.sp.sub(1);
+                        self.sp = self.sp.sub(1);
+                    }
+                    INSN_TUCK => {
+                        self.require::<CHECKED>(2)?;
+                        let value = *self.sp.sub(1);
+                        self.push::<CHECKED>(value)?;
+                        *self.sp.sub(2) = *self.sp.sub(3);
+                        *self.sp.sub(3) = value;
+                    }
+                    INSN_DUP2 => {
+                        self.require::<CHECKED>(2)?;
+                        let x = *self.sp.sub(1);
+                        let y = *self.sp.sub(2);
+                        self.push::<CHECKED>(y)?;
+                        self.push::<CHECKED>(x)?;
+                    }
+                    INSN_DROP2 => {
+                        self.require::<CHECKED>(2)?;
+                        self.sp = self.sp.sub(2);
+                    }
+                    INSN_SWAP2 => {
+                        self.require::<CHECKED>(4)?;
+                        std::slice::from_raw_parts_mut(self.sp.sub(4), 4).rotate_left(2);
+                    }
+                    INSN_PICK => {
+                        let depth = self.fetch_const::<CHECKED>()?.int as usize;
+                        self.require::<CHECKED>(depth.saturating_add(1))?;
+                        let value = *self.sp.sub(depth + 1);
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_ROLL => {
+                        let depth = self.fetch_const::<CHECKED>()?.int as usize;
+                        self.require::<CHECKED>(depth.saturating_add(1))?;
+                        std::slice::from_raw_parts_mut(self.sp.sub(depth + 1), depth + 1)
+                            .rotate_left(1);
+                    }
+                    INSN_LOAD_LOCAL => {
+                        let index = self.fetch_const::<CHECKED>()?.int;
+                        let Some(&value) = usize::try_from(index)
+                            .ok()
+                            .filter(|&index| index < MAX_LOCALS)
+                            .and_then(|index| self.locals.get(self.frame + index))
+                        else {
+                            return Err(VmError::InvalidLocal { index });
+                        };
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_STORE_LOCAL => {
+                        let index = self.fetch_const::<CHECKED>()?.int;
+                        let value = self.pop::<CHECKED>()?;
+                        let index = self.local(index)?;
+                        self.locals[index] = value;
+                    }
+                    INSN_GLOAD => {
+                        let index = self.fetch_const::<CHECKED>()?.int;
+                        let value = *self.global(index)?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_GSTORE => {
+                        let index = self.fetch_const::<CHECKED>()?.int;
+                        let value = self.pop::<CHECKED>()?;
+                        *self.global(index)? = value;
+                    }
+                    INSN_PUSH_I64 | INSN_PUSH_F64 | INSN_PUSH_BOOL | INSN_PUSH_ADDR => {
+                        let value = self.fetch_const::<CHECKED>()?;
+                        self.push::<CHECKED>(value)?;
                     }
                     INSN_NUMCONV_I64 => {
+                        self.require::<CHECKED>(1)?;
                         *self.sp.sub(1) = Value {
                             int: (*self.sp.sub(1)).float as i64,
                         };
                     }
                     INSN_NUMCONV_F64 => {
+                        self.require::<CHECKED>(1)?;
                         *self.sp.sub(1) = Value {
                             float: (*self.sp.sub(1)).int as f64,
                         };
                     }
-                    INSN_ABORT => (self.panic_handler)(PanicInfo::Abort { vm: self }),
+                    INSN_NUMCONV_BOOL | INSN_BOOLCONV_I64 => {
+                        self.require::<CHECKED>(1)?;
+                        *self.sp.sub(1) = Value {
+                            int: ((*self.sp.sub(1)).int != 0) as i64,
+                        };
+                    }
+                    INSN_NUMCONV_STR => {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let value = self.alloc_string(x.to_string())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_FLOATCONV_STR => {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let value = self.alloc_string(x.to_string())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_STRCONV_I64 => {
+                        let x = self.pop::<CHECKED>()?;
+                        let parsed = self.string(x)?.parse::<i64>();
+                        self.push::<CHECKED>(Value {
+                            int: *parsed.as_ref().unwrap_or(&0),
+                        })?;
+                        self.push::<CHECKED>(Value {
+                            int: parsed.is_ok() as i64,
+                        })?;
+                    }
+                    INSN_STRCONV_F64 => {
+                        let x = self.pop::<CHECKED>()?;
+                        let parsed = self.string(x)?.parse::<f64>();
+                        self.push::<CHECKED>(Value {
+                            float: *parsed.as_ref().unwrap_or(&0.0),
+                        })?;
+                        self.push::<CHECKED>(Value {
+                            int: parsed.is_ok() as i64,
+                        })?;
+                    }
+                    // Code addresses are integers at runtime
+                    INSN_NUMCONV_ADDR | INSN_ADDRCONV_I64 => self.require::<CHECKED>(1)?,
+                    INSN_ABORT => return Ok(ExitStatus::Abort),
                     INSN_EXIT => {
-                        self.sp = self.sp.sub(1);
-                        let code = (*self.sp).int;
-                        (self.panic_handler)(PanicInfo::Exit { vm: self, code });
+                        return Ok(ExitStatus::Exit(self.pop::<CHECKED>()?.int));
                     }
                     INSN_PANIC => {
-                        self.sp = self.sp.sub(1);
-                        let msg = (*self.sp).string;
-                        (self.panic_handler)(PanicInfo::Panic { vm: self, msg });
+                        let msg = self.pop::<CHECKED>()?;
+                        return Ok(ExitStatus::Panic(self.string(msg)?.clone()));
                     }
                     INSN_PRINTLN => {
-                        stdout.write_all(&[0xA]).expect("Write to stdout");
-                        stdout.flush().expect("Write to stdout");
+                        stdout.write_all(&[0xA])?;
+                        stdout.flush()?;
                     }
                     INSN_INPUT => {
-                        let mut buf = String::with_capacity(self.flags.prealloc);
-                        stdin.read_line(&mut buf).expect("Read from stdin");
+                        let mut buf = String::new();
+                        stdin.read_line(&mut buf)?;
                         if buf.ends_with('\n') {
                             buf.pop().unwrap();
                         }
                         if buf.ends_with('\r') {
                             buf.pop().unwrap();
                         }
-                        *self.sp = self.alloc_string(buf);
-                        self.sp = self.sp.add(1);
+                        let value = self.alloc_string(buf)?;
+                        self.push::<CHECKED>(value)?;
                     }
                     INSN_GC => self.collect_garbage(),
+                    INSN_NATIVE => {
+                        let index = self.fetch_const::<CHECKED>()?.int;
+                        let Some(native) = usize::try_from(index)
+                            .ok()
+                            .and_then(|index| linked.get(index))
+                        else {
+                            return Err(VmError::InvalidImport { index });
+                        };
+                        self.call_native(*native)?;
+                    }
                     INSN_PRINT_I64 => {
-                        self.sp = self.sp.sub(1);
-                        stdout
-                            .write_all((*self.sp).int.to_string().as_bytes())
-                            .expect("Write to stdout");
-                        stdout.flush().expect("Write to stdout");
+                        let x = self.pop::<CHECKED>()?;
+                        stdout.write_all(x.int.to_string().as_bytes())?;
+                        stdout.flush()?;
                     }
                     INSN_PRINT_F64 => {
-                        self.sp = self.sp.sub(1);
-                        stdout
-                            .write_all((*self.sp).float.to_string().as_bytes())
-                            .expect("Write to stdout");
-                        stdout.flush().expect("Write to stdout");
+                        let x = self.pop::<CHECKED>()?;
+                        stdout.write_all(x.float.to_string().as_bytes())?;
+                        stdout.flush()?;
                     }
                     INSN_PRINT_STR => {
-                        self.sp = self.sp.sub(1);
-                        stdout
-                            .write_all((*(*self.sp).string).as_bytes())
-                            .expect("Write to stdout");
-                        stdout.flush().expect("Write to stdout");
-                    }
-                    INSN_ADD_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value { int: y + x });
-                    }
-                    INSN_SUB_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value { int: y - x });
-                    }
-                    INSN_MUL_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value { int: y * x });
-                    }
-                    INSN_DIV_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value { int: y / x });
+                        let x = self.pop::<CHECKED>()?;
+                        stdout.write_all(self.string(x)?.as_bytes())?;
+                        stdout.flush()?;
+                    }
+                    INSN_PRINT_BOOL => {
+                        let x = self.pop::<CHECKED>()?;
+                        stdout.write_all(if x.int != 0 { b"true" } else { b"false" })?;
+                        stdout.flush()?;
+                    }
+                    INSN_ADD_I64 => self.int_op::<CHECKED>(|y, x| {
+                        y.checked_add(x).ok_or(VmError::IntegerOverflow)
+                    })?,
+                    INSN_SUB_I64 => self.int_op::<CHECKED>(|y, x| {
+                        y.checked_sub(x).ok_or(VmError::IntegerOverflow)
+                    })?,
+                    INSN_MUL_I64 => self.int_op::<CHECKED>(|y, x| {
+                        y.checked_mul(x).ok_or(VmError::IntegerOverflow)
+                    })?,
+                    INSN_DIV_I64 => self.int_op::<CHECKED>(|y, x| {
+                        y.checked_div(divisor(x)?).ok_or(VmError::IntegerOverflow)
+                    })?,
+                    INSN_ADD_WRAP_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.wrapping_add(x)))?,
+                    INSN_SUB_WRAP_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.wrapping_sub(x)))?,
+                    INSN_MUL_WRAP_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.wrapping_mul(x)))?,
+                    INSN_DIV_WRAP_I64 => {
+                        self.int_op::<CHECKED>(|y, x| Ok(y.wrapping_div(divisor(x)?)))?
+                    }
+                    INSN_ADD_SAT_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.saturating_add(x)))?,
+                    INSN_SUB_SAT_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.saturating_sub(x)))?,
+                    INSN_MUL_SAT_I64 => self.int_op::<CHECKED>(|y, x| Ok(y.saturating_mul(x)))?,
+                    INSN_DIV_SAT_I64 => {
+                        self.int_op::<CHECKED>(|y, x| Ok(y.saturating_div(divisor(x)?)))?
                     }
                     INSN_ADD_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value { float: y + x });
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: y + x })?;
                     }
                     INSN_SUB_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value { float: y - x });
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: y - x })?;
                     }
                     INSN_MUL_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value { float: y * x });
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: y * x })?;
                     }
                     INSN_DIV_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value { float: y / x });
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: y / x })?;
+                    }
+                    INSN_MOD_I64 => self.int_op::<CHECKED>(|y, x| {
+                        y.checked_rem(divisor(x)?).ok_or(VmError::IntegerOverflow)
+                    })?,
+                    INSN_MOD_F64 => {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: y % x })?;
+                    }
+                    INSN_NEG_I64 => {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let Some(int) = x.checked_neg() else {
+                            return Err(VmError::IntegerOverflow);
+                        };
+                        self.push::<CHECKED>(Value { int })?;
+                    }
+                    INSN_NEG_F64 => {
+                        let x = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value { float: -x })?;
+                    }
+                    INSN_AND_I64 => self.int_op::<CHECKED>(|y, x| Ok(y & x))?,
+                    INSN_OR_I64 => self.int_op::<CHECKED>(|y, x| Ok(y | x))?,
+                    INSN_XOR_I64 => self.int_op::<CHECKED>(|y, x| Ok(y ^ x))?,
+                    INSN_NOT_I64 => {
+                        let x = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value { int: !x })?;
+                    }
+                    INSN_SHL_I64 => self.int_op::<CHECKED>(|y, x| Ok(y << shift_amount(x)?))?,
+                    INSN_SHR_I64 => self.int_op::<CHECKED>(|y, x| Ok(y >> shift_amount(x)?))?,
+                    INSN_AND_BOOL => {
+                        self.int_op::<CHECKED>(|y, x| Ok((y != 0 && x != 0) as i64))?
+                    }
+                    INSN_OR_BOOL => self.int_op::<CHECKED>(|y, x| Ok((y != 0 || x != 0) as i64))?,
+                    INSN_NOT_BOOL => {
+                        let x = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
+                            int: (x == 0) as i64,
+                        })?;
                     }
                     INSN_ADD_STR => {
-                        let x = self.pop().string;
-                        let y = self.pop().string;
-                        let a = self.alloc_string((*y).clone() + (*x).as_str());
-                        self.push(a);
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let a = self.alloc_string(self.string(y)?.clone() + self.string(x)?)?;
+                        self.push::<CHECKED>(a)?;
                     }
                     INSN_EQ_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let y = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
                             int: (x == y) as i64,
-                        });
+                        })?;
                     }
                     INSN_LT_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let y = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
                             int: (y < x) as i64,
-                        });
+                        })?;
                     }
                     INSN_GT_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let y = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
                             int: (y > x) as i64,
-                        });
+                        })?;
                     }
                     INSN_LE_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let y = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
                             int: (y <= x) as i64,
-                        });
+                        })?;
                     }
                     INSN_GE_I64 => {
-                        let x = self.pop().int;
-                        let y = self.pop().int;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.int;
+                        let y = self.pop::<CHECKED>()?.int;
+                        self.push::<CHECKED>(Value {
                             int: (y >= x) as i64,
-                        });
+                        })?;
                     }
                     INSN_EQ_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value {
                             int: (y == x) as i64,
-                        });
+                        })?;
                     }
                     INSN_LT_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value {
                             int: (y < x) as i64,
-                        });
+                        })?;
                     }
                     INSN_GT_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value {
                             int: (y > x) as i64,
-                        });
+                        })?;
                     }
                     INSN_LE_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value {
                             int: (y <= x) as i64,
-                        });
+                        })?;
                     }
                     INSN_GE_F64 => {
-                        let x = self.pop().float;
-                        let y = self.pop().float;
-                        self.push(Value {
+                        let x = self.pop::<CHECKED>()?.float;
+                        let y = self.pop::<CHECKED>()?.float;
+                        self.push::<CHECKED>(Value {
                             int: (y >= x) as i64,
-                        });
+                        })?;
                     }
                     INSN_EQ_STR => {
-                        let x = self.pop().string;
-                        let y = self.pop().string;
-                        self.push(Value {
-                            int: (*x == *y) as i64,
-                        });
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let eq = self.string(x)? == self.string(y)?;
+                        self.push::<CHECKED>(Value { int: eq as i64 })?;
+                    }
+                    INSN_LT_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let lt = self.string(y)? < self.string(x)?;
+                        self.push::<CHECKED>(Value { int: lt as i64 })?;
+                    }
+                    INSN_GT_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let gt = self.string(y)? > self.string(x)?;
+                        self.push::<CHECKED>(Value { int: gt as i64 })?;
+                    }
+                    INSN_LEN_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let len = self.string(x)?.chars().count();
+                        self.push::<CHECKED>(Value { int: len as i64 })?;
+                    }
+                    INSN_SUBSTR_STR => {
+                        let len = self.pop::<CHECKED>()?.int;
+                        let start = self.pop::<CHECKED>()?.int;
+                        let x = self.pop::<CHECKED>()?;
+                        let string = self.string(x)?;
+                        let (from, _) = char_range(string, start)?;
+                        let (to, _) = char_range(string, start.saturating_add(len))?;
+                        if to < from {
+                            return Err(VmError::IndexOutOfBounds {
+                                index: start.saturating_add(len),
+                                len: string.chars().count(),
+                            });
+                        }
+                        let value = self.alloc_string(string[from..to].to_string())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_CHAR_AT_STR => {
+                        let index = self.pop::<CHECKED>()?.int;
+                        let x = self.pop::<CHECKED>()?;
+                        let string = self.string(x)?;
+                        let (from, to) = char_range(string, index)?;
+                        if from == to {
+                            return Err(VmError::IndexOutOfBounds {
+                                index,
+                                len: string.chars().count(),
+                            });
+                        }
+                        let value = self.alloc_string(string[from..to].to_string())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_FIND_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let string = self.string(y)?;
+                        let index = match string.find(self.string(x)?.as_str()) {
+                            Some(offset) => string[..offset].chars().count() as i64,
+                            None => -1,
+                        };
+                        self.push::<CHECKED>(Value { int: index })?;
+                    }
+                    INSN_STARTS_WITH_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let starts = self.string(y)?.starts_with(self.string(x)?.as_str());
+                        self.push::<CHECKED>(Value { int: starts as i64 })?;
+                    }
+                    INSN_SPLIT_FIRST_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let y = self.pop::<CHECKED>()?;
+                        let string = self.string(y)?;
+                        let (before, after, found) = match string
+                            .split_once(self.string(x)?.as_str())
+                        {
+                            Some((before, after)) => (before.to_string(), after.to_string(), true),
+                            None => (string.clone(), String::new(), false),
+                        };
+                        // Each part is pushed before the next allocation, which may collect garbage
+                        let before = self.alloc_string(before)?;
+                        self.push::<CHECKED>(before)?;
+                        let after = self.alloc_string(after)?;
+                        self.push::<CHECKED>(after)?;
+                        self.push::<CHECKED>(Value { int: found as i64 })?;
+                    }
+                    INSN_TRIM_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let value = self.alloc_string(self.string(x)?.trim().to_string())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_UPPER_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let value = self.alloc_string(self.string(x)?.to_uppercase())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_LOWER_STR => {
+                        let x = self.pop::<CHECKED>()?;
+                        let value = self.alloc_string(self.string(x)?.to_lowercase())?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_NEW_ARRAY_I64 | INSN_NEW_ARRAY_F64 | INSN_NEW_ARRAY_STR
+                    | INSN_NEW_ARRAY_BOOL => {
+                        let element = element_type(insn).unwrap();
+                        let array = self.alloc_array(element)?;
+                        self.push::<CHECKED>(array)?;
+                    }
+                    INSN_GET_I64 | INSN_GET_F64 | INSN_GET_STR | INSN_GET_BOOL => {
+                        let index = self.pop::<CHECKED>()?.int;
+                        let array = self.pop::<CHECKED>()?;
+                        let value = *self.element(array, index)?;
+                        self.push::<CHECKED>(value)?;
+                    }
+                    INSN_SET_I64 | INSN_SET_F64 | INSN_SET_STR | INSN_SET_BOOL => {
+                        let value = self.pop::<CHECKED>()?;
+                        let index = self.pop::<CHECKED>()?.int;
+                        let array = self.pop::<CHECKED>()?;
+                        *self.element(array, index)? = value;
+                    }
+                    INSN_ARRAY_LEN_I64 | INSN_ARRAY_LEN_F64 | INSN_ARRAY_LEN_STR
+                    | INSN_ARRAY_LEN_BOOL => {
+                        let array = self.pop::<CHECKED>()?;
+                        let len = self.array(array)?.len();
+                        self.push::<CHECKED>(Value { int: len as i64 })?;
+                    }
+                    INSN_APPEND_I64 | INSN_APPEND_F64 | INSN_APPEND_STR | INSN_APPEND_BOOL => {
+                        // Collect while the array and the value are still on the stack
+                        self.require::<CHECKED>(2)?;
+                        if self.heap.needs_collection(size_of::<Value>()) {
+                            self.collect_garbage();
+                        }
+                        let value = self.pop::<CHECKED>()?;
+                        let array = self.pop::<CHECKED>()?;
+                        self.array(array)?;
+                        if !self.heap.push(array.array, value) {
+                            return Err(VmError::OutOfMemory {
+                                size: size_of::<Value>(),
+                            });
+                        }
                     }
                     INSN_J => {
-                        self.pc = self.pop().int as _;
+                        let addr = self.pop::<CHECKED>()?.int;
+                        self.jump(addr)?;
                     }
                     INSN_JNZ => {
-                        self.sp = self.sp.sub(2);
-                        if (*self.sp).int != 0 {
-                            self.pc = (*self.sp.add(1)).int as _;
+                        let addr = self.pop::<CHECKED>()?.int;
+                        if self.pop::<CHECKED>()?.int != 0 {
+                            self.jump(addr)?;
                         }
                     }
                     INSN_JZ => {
-                        self.sp = self.sp.sub(2);
-                        if (*self.sp).int == 0 {
-                            self.pc = (*self.sp.add(1)).int as _;
+                        let addr = self.pop::<CHECKED>()?.int;
+                        if self.pop::<CHECKED>()?.int == 0 {
+                            self.jump(addr)?;
                         }
                     }
                     INSN_CALL => {
-                        let addr = self.pop().int;
-                        self.push(Value { int: self.pc as _ });
-                        self.pc = addr as _;
+                        let addr = self.pop::<CHECKED>()?.int;
+                        if self.return_stack.len() == MAX_CALL_DEPTH {
+                            return Err(VmError::ReturnStackOverflow);
+                        }
+                        self.return_stack.push((self.pc, self.frame));
+                        self.frame = self.locals.len();
+                        self.jump(addr)?;
+                    }
+                    INSN_RET => {
+                        let Some((addr, frame)) = self.return_stack.pop() else {
+                            return Err(VmError::InvalidReturn);
+                        };
+                        self.locals.truncate(self.frame);
+                        self.frame = frame;
+                        self.pc = addr;
                     }
                     insn => {
-                        (self.panic_handler)(PanicInfo::IllegalInstruction { vm: self, insn });
+                        return Err(VmError::IllegalInstruction { insn });
                     }
                 }
             }
         }
+        Ok(ExitStatus::Exit(0))
+    }
+}
+
+/// Value of a global of the type before it is first stored
+fn initial_value(heap: &mut Heap, type_: Type) -> Result<Value, VmError> {
+    let out_of_memory = || VmError::OutOfMemory {
+        size: OBJECT_OVERHEAD,
+    };
+    Ok(match type_ {
+        Type::Float => Value { float: 0.0 },
+        Type::String => Value {
+            string: heap.alloc(String::new()).ok_or_else(out_of_memory)?,
+        },
+        Type::Array(element) => Value {
+            array: heap.alloc_array(*element).ok_or_else(out_of_memory)?,
+        },
+        _ => Value { int: 0 },
+    })
+}
+
+fn divisor(x: i64) -> Result<i64, VmError> {
+    if x == 0 {
+        return Err(VmError::DivisionByZero);
+    }
+    Ok(x)
+}
+
+/// Returns the byte range of the character at the index, which is empty at the end of the string
+fn char_range(string: &str, index: i64) -> Result<(usize, usize), VmError> {
+    let out_of_bounds = || VmError::IndexOutOfBounds {
+        index,
+        len: string.chars().count(),
+    };
+    let index = usize::try_from(index).map_err(|_| out_of_bounds())?;
+    let mut offsets = string
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([string.len()])
+        .skip(index);
+    let from = offsets.next().ok_or_else(out_of_bounds)?;
+    Ok((from, offsets.next().unwrap_or(from)))
+}
+
+fn shift_amount(x: i64) -> Result<i64, VmError> {
+    if !(0..64).contains(&x) {
+        return Err(VmError::InvalidShift { amount: x });
     }
+    Ok(x)
 }
 
 impl Drop for Runtime<'_> {
@@ -391,29 +1024,170 @@ impl Drop for Runtime<'_> {
     }
 }
 
-pub enum PanicInfo<'a, 'b: 'a> {
-    Pop {
-        vm: &'a mut Runtime<'b>,
-        expected: u32,
-        got: u32,
-    },
-    IllegalInstruction {
-        vm: &'a mut Runtime<'b>,
-        insn: u16,
-    },
-    Abort {
-        vm: &'a mut Runtime<'b>,
-    },
-    Exit {
-        vm: &'a mut Runtime<'b>,
-        code: i64,
-    },
-    Panic {
-        vm: &'a mut Runtime<'b>,
-        msg: *const String,
-    },
-    InvalidConstant {
-        vm: &'a mut Runtime<'b>,
-        index: i64,
-    },
+/// How a program ended
+#[derive(Debug, PartialEq)]
+pub enum ExitStatus {
+    Exit(i64),
+    Abort,
+    Panic(String),
+}
+
+/// Fault that stopped the virtual machine
+#[derive(Debug, PartialEq)]
+pub enum VmError {
+    IllegalInstruction { insn: u16 },
+    InvalidConstant { index: i64 },
+    InvalidString { handle: Handle },
+    InvalidArray { handle: Handle },
+    OutOfMemory { size: usize },
+    InvalidJump { target: i64 },
+    InvalidReturn,
+    ReturnStackOverflow,
+    StackOverflow,
+    StackUnderflow,
+    IntegerOverflow,
+    DivisionByZero,
+    InvalidShift { amount: i64 },
+    IndexOutOfBounds { index: i64, len: usize },
+    InvalidLocal { index: i64 },
+    InvalidGlobal { index: i64 },
+    TruncatedInstruction,
+    InvalidImport { index: i64 },
+    UnknownNative { name: String },
+    NativeSignatureMismatch { name: String },
+    Native { name: String, message: String },
+    Io { message: String },
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::IllegalInstruction { insn } => write!(f, "illegal instruction 0x{insn:04X}"),
+            VmError::InvalidConstant { index } => write!(f, "invalid constant index {index}"),
+            VmError::InvalidString { handle } => write!(
+                f,
+                "invalid string handle {}:{}",
+                handle.index, handle.generation
+            ),
+            VmError::InvalidArray { handle } => write!(
+                f,
+                "invalid array handle {}:{}",
+                handle.index, handle.generation
+            ),
+            VmError::OutOfMemory { size } => {
+                write!(f, "out of memory allocating {size} bytes")
+            }
+            VmError::InvalidJump { target } => write!(f, "invalid jump target 0x{target:08X}"),
+            VmError::InvalidReturn => write!(f, "return with empty return stack"),
+            VmError::ReturnStackOverflow => write!(f, "return stack overflow"),
+            VmError::StackOverflow => write!(f, "stack overflow"),
+            VmError::StackUnderflow => write!(f, "stack underflow"),
+            VmError::IntegerOverflow => write!(f, "integer overflow"),
+            VmError::DivisionByZero => write!(f, "division by zero"),
+            VmError::InvalidShift { amount } => write!(f, "invalid shift amount {amount}"),
+            VmError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {index} out of bounds of length {len}")
+            }
+            VmError::InvalidLocal { index } => write!(f, "invalid local {index}"),
+            VmError::InvalidGlobal { index } => write!(f, "invalid global {index}"),
+            VmError::TruncatedInstruction => write!(f, "truncated instruction"),
+            VmError::InvalidImport { index } => write!(f, "invalid import index {index}"),
+            VmError::UnknownNative { name } => write!(f, "unknown native word '{name}'"),
+            VmError::NativeSignatureMismatch { name } => {
+                write!(f, "mismatched signature of native word '{name}'")
+            }
+            VmError::Native { name, message } => {
+                write!(f, "error in native word '{name}': {message}")
+            }
+            VmError::Io { message } => write!(f, "io error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<io::Error> for VmError {
+    fn from(err: io::Error) -> Self {
+        VmError::Io {
+            message: err.to_string(),
+        }
+    }
+}
+
+struct Native<'a> {
+    name: String,
+    signature: Signature,
+    /// Taken while the function is running
+    function: Option<NativeFunction<'a>>,
+}
+
+/// Access to the stack of the virtual machine for native functions.
+///
+/// Arguments are popped from the top of the stack and have to match the signature of the word, so
+/// do the pushed results.
+pub struct NativeStack<'r, 'a> {
+    vm: &'r mut Runtime<'a>,
+    /// Arguments that have not been popped yet
+    inputs: Vec<Type>,
+    outputs: Vec<Type>,
+}
+
+impl NativeStack<'_, '_> {
+    pub fn pop_int(&mut self) -> Result<i64, String> {
+        Ok(unsafe { self.pop(Type::Int)?.int })
+    }
+
+    pub fn pop_float(&mut self) -> Result<f64, String> {
+        Ok(unsafe { self.pop(Type::Float)?.float })
+    }
+
+    pub fn pop_bool(&mut self) -> Result<bool, String> {
+        Ok(unsafe { self.pop(Type::Bool)?.int != 0 })
+    }
+
+    pub fn pop_string(&mut self) -> Result<String, String> {
+        let value = self.pop(Type::String)?;
+        self.vm
+            .string(value)
+            .cloned()
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn push_int(&mut self, value: i64) -> Result<(), String> {
+        self.push(Type::Int, Value { int: value })
+    }
+
+    pub fn push_float(&mut self, value: f64) -> Result<(), String> {
+        self.push(Type::Float, Value { float: value })
+    }
+
+    pub fn push_bool(&mut self, value: bool) -> Result<(), String> {
+        self.push(Type::Bool, Value { int: value as i64 })
+    }
+
+    pub fn push_string(&mut self, value: String) -> Result<(), String> {
+        let value = self.vm.alloc_string(value).map_err(|err| err.to_string())?;
+        self.push(Type::String, value)
+    }
+
+    /// Stack accesses of natives are always checked, their signatures are only known at runtime
+    fn push(&mut self, type_: Type, value: Value) -> Result<(), String> {
+        self.outputs.push(type_);
+        self.vm.push::<true>(value).map_err(|err| err.to_string())
+    }
+
+    /// Arguments have to be popped before the first result is pushed, which would be popped instead
+    fn pop(&mut self, type_: Type) -> Result<Value, String> {
+        if !self.outputs.is_empty() {
+            return Err(format!("Popped {type_:?} after pushing results"));
+        }
+        match self.inputs.last() {
+            Some(x) if *x == type_ => {
+                self.inputs.pop();
+                self.vm.pop::<true>().map_err(|err| err.to_string())
+            }
+            Some(x) => Err(format!("Popped {type_:?} but the argument is {x:?}")),
+            None => Err(format!("Popped {type_:?} but there are no arguments left")),
+        }
+    }
 }
@@ -1,65 +1,43 @@
-use std::{
-    io::{Read, Result},
-    process::exit,
-};
+use cacas::{ExitStatus, GcStats, Runtime, VmError};
 
-use byteorder::{LittleEndian, ReadBytesExt};
-
-use crate::runtime::{PanicInfo, Runtime};
-
-pub fn default_panic_handler(info: PanicInfo) -> ! {
-    match info {
-        PanicInfo::Pop { vm, expected, got } => {
-            println!("Virtual machine paniced at pop instruction");
-            dump_vm(vm);
-            println!("panic {{");
-            println!("  expected: {expected}");
-            println!("  got: {got}");
-            println!("}}");
-        }
-        PanicInfo::IllegalInstruction { vm, insn } => {
-            println!("Virtual machine paniced with illegal instruction");
-            dump_vm(vm);
-            println!("panic {{");
-            println!("  insn: 0x{insn:04X}");
-            println!("}}");
-        }
-        PanicInfo::Abort { vm } => {
+/// Prints how the program ended and returns the exit code for the process
+pub fn report(vm: &Runtime, result: Result<ExitStatus, VmError>) -> i32 {
+    match result {
+        Ok(ExitStatus::Exit(code)) => return code as _,
+        Ok(ExitStatus::Abort) => {
             println!("Virtual machine aborted");
             dump_vm(vm);
         }
-        PanicInfo::Exit { vm: _, code } => {
-            exit(code as _);
-        }
-        PanicInfo::Panic { vm, msg } => {
-            println!("Virtual machine paniced: '{}'", unsafe { (*msg).as_str() });
+        Ok(ExitStatus::Panic(msg)) => {
+            println!("Virtual machine paniced: '{msg}'");
             dump_vm(vm);
         }
-        PanicInfo::InvalidConstant { vm, index } => {
-            println!("Virtual machine paniced with invalid constant index {index}");
+        // Standard output may be gone, so nothing more is printed to it
+        Err(err @ VmError::Io { .. }) => eprintln!("Virtual machine stopped with {err}"),
+        Err(err) => {
+            println!("Virtual machine paniced with {err}");
             dump_vm(vm);
         }
     }
-    exit(-1);
+    -1
 }
 
-pub fn read_string_constants(read: &mut impl Read) -> Result<Vec<String>> {
-    let len = read.read_u64::<LittleEndian>()?;
-    let mut constants = Vec::with_capacity(len as _);
-    for _ in 0..len {
-        let str_len = read.read_u64::<LittleEndian>()?;
-        let mut buf = String::with_capacity(str_len as _);
-        read.take(str_len).read_to_string(&mut buf)?;
-        constants.push(buf);
+fn dump_vm(vm: &Runtime) {
+    println!("vm {{");
+    println!("  stack_len: {}", vm.stack_len());
+    println!("  program_counter: 0x{:08X}", vm.pc());
+    if let Some(location) = vm.location() {
+        println!("  location: {location}");
     }
-    Ok(constants)
+    println!("  stack_size: {}", vm.stack_size());
+    println!("  heap_size: {}", vm.heap_size());
+    println!("}}");
 }
 
-fn dump_vm(vm: &Runtime) {
-    println!("vm {{");
-    println!("  base_pointer: 0x{:012X}", vm.bp as usize);
-    println!("  stack_pointer: 0x{:012X}", vm.sp as usize);
-    println!("  program_counter: 0x{:08X}", vm.pc);
-    println!("  stack_size: {}", vm.stack_size);
+pub fn print_gc_stats(stats: &GcStats) {
+    println!("gc {{");
+    println!("  collections: {}", stats.collections);
+    println!("  bytes_freed: {}", stats.bytes_freed);
+    println!("  peak_size: {}", stats.peak_size);
     println!("}}");
 }
@@ -0,0 +1,56 @@
+use cacas::{ExitStatus, Flags, Program, VmError};
+
+const MIN: &str = "-9223372036854775808";
+const MAX: &str = "9223372036854775807";
+
+/// Runs the source with `MIN` and `MAX` replaced by the bounds of integers
+fn run(source: &str) -> Result<ExitStatus, VmError> {
+    let source = source.replace("MIN", MIN).replace("MAX", MAX);
+    let program = Program::compile(&source, None, &Flags::default(), &[]).unwrap();
+    let mut vm = program.runtime(Flags::default()).unwrap();
+    vm.execute()
+}
+
+#[test]
+fn overflow_faults() {
+    for source in [
+        "MAX 1 + exit",
+        "MIN 1 - exit",
+        "MAX 2 * exit",
+        "MIN -1 / exit",
+        "MIN -1 mod exit",
+        "MIN neg exit",
+    ] {
+        assert_eq!(run(source), Err(VmError::IntegerOverflow), "{source}");
+    }
+}
+
+#[test]
+fn division_by_zero_faults() {
+    for source in [
+        "1 0 / exit",
+        "1 0 mod exit",
+        "1 0 wrap/ exit",
+        "1 0 sat/ exit",
+    ] {
+        assert_eq!(run(source), Err(VmError::DivisionByZero), "{source}");
+    }
+}
+
+#[test]
+fn wrapping_arithmetic_wraps_around() {
+    assert_eq!(run("MAX 1 wrap+ exit"), Ok(ExitStatus::Exit(i64::MIN)));
+    assert_eq!(run("MIN 1 wrap- exit"), Ok(ExitStatus::Exit(i64::MAX)));
+    assert_eq!(run("MAX 2 wrap* exit"), Ok(ExitStatus::Exit(-2)));
+    assert_eq!(run("MIN -1 wrap/ exit"), Ok(ExitStatus::Exit(i64::MIN)));
+    assert_eq!(run("7 2 wrap/ exit"), Ok(ExitStatus::Exit(3)));
+}
+
+#[test]
+fn saturating_arithmetic_stops_at_the_bounds() {
+    assert_eq!(run("MAX 1 sat+ exit"), Ok(ExitStatus::Exit(i64::MAX)));
+    assert_eq!(run("MIN 1 sat- exit"), Ok(ExitStatus::Exit(i64::MIN)));
+    assert_eq!(run("MIN 2 sat* exit"), Ok(ExitStatus::Exit(i64::MIN)));
+    assert_eq!(run("MIN -1 sat/ exit"), Ok(ExitStatus::Exit(i64::MAX)));
+    assert_eq!(run("-7 2 sat/ exit"), Ok(ExitStatus::Exit(-3)));
+}
@@ -0,0 +1,44 @@
+use cacas::{ExitStatus, Flags, Program, VmError};
+
+fn run(source: &str, flags: Flags) -> Result<ExitStatus, VmError> {
+    let program = Program::compile(source, None, &flags, &[]).unwrap();
+    let mut vm = program.runtime(flags).unwrap();
+    vm.execute()
+}
+
+#[test]
+fn indices_outside_of_arrays_fault() {
+    let source = "fn f ( -- ) new-array int let a a 7 push a 1 get drop end f";
+    assert_eq!(
+        run(source, Flags::default()),
+        Err(VmError::IndexOutOfBounds { index: 1, len: 1 })
+    );
+    let source = "fn f ( -- ) new-array int let a a -1 7 set end f";
+    assert_eq!(
+        run(source, Flags::default()),
+        Err(VmError::IndexOutOfBounds { index: -1, len: 0 })
+    );
+}
+
+#[test]
+fn strings_in_arrays_survive_collections() {
+    let source = r#"
+fn f ( -- int )
+    new-array str let a
+    0 let i
+    while i 100 < do
+        a "s" i ~str + push
+        "garbage" i ~str + drop
+        i 1 + i!
+    end
+    gc
+    a 0 "first" set
+    a 0 get "first" = a 99 get "s99" = and a len 100 = and
+    if 0 else 1 end
+end
+f exit
+"#;
+    let mut flags = Flags::default();
+    flags.heap.initial_size = 256;
+    assert_eq!(run(source, flags), Ok(ExitStatus::Exit(0)));
+}
@@ -0,0 +1,41 @@
+use cacas::{Flags, Program};
+
+const SOURCE: &str = "fn f ( int int int -- int int int int ) pick 2 roll 3 end
+:top 1 2 3 f drop drop drop drop &top drop";
+
+fn disassemble(file: Option<&str>) -> String {
+    let program = Program::compile(SOURCE, file, &Flags::default(), &[]).unwrap();
+    let mut bytes = Vec::new();
+    program.write(&mut bytes).unwrap();
+    let mut listing = Vec::new();
+    Program::load(&bytes)
+        .unwrap()
+        .disassemble(&mut listing)
+        .unwrap();
+    String::from_utf8(listing).unwrap()
+}
+
+#[test]
+fn lists_compiled_binaries() {
+    let listing = disassemble(Some("a.roth"));
+    assert!(listing.contains("; source: a.roth\n"), "{listing}");
+    assert!(listing.contains(
+        ":f
+0000000C  pick        2
+00000016  roll        3
+00000020  ret
+:top
+00000022  push_i64    1
+"
+    ));
+    assert!(listing.contains("00000040  push_addr   12                   ; -> :f\n"));
+    assert!(listing.contains("00000054  push_addr   34                   ; -> :top\n"));
+}
+
+#[test]
+fn labels_need_debug_information() {
+    let listing = disassemble(None);
+    assert!(!listing.contains("; source:"), "{listing}");
+    assert!(!listing.contains(":top"));
+    assert!(listing.contains("; -> :f\n"));
+}
@@ -0,0 +1,58 @@
+use cacas::{ExitStatus, Flags, Program, VmError};
+
+fn run(source: &str, flags: Flags) -> Result<ExitStatus, VmError> {
+    let program = Program::compile(source, None, &flags, &[]).unwrap();
+    let mut vm = program.runtime(flags).unwrap();
+    vm.execute()
+}
+
+fn noverify() -> Flags {
+    Flags {
+        verify: false,
+        ..Flags::default()
+    }
+}
+
+#[test]
+// Too slow for the thousands of frames
+#[cfg_attr(miri, ignore)]
+fn unbounded_recursion_overflows_return_stack() {
+    let source = "fn g ( -- ) g end g";
+    assert_eq!(
+        run(source, Flags::default()),
+        Err(VmError::ReturnStackOverflow)
+    );
+    assert_eq!(run(source, noverify()), Err(VmError::ReturnStackOverflow));
+}
+
+#[test]
+// Too slow for the thousands of frames
+#[cfg_attr(miri, ignore)]
+fn unbounded_locals_overflow() {
+    let locals: String = (0..32).map(|i| format!("{i} let x{i} ")).collect();
+    let source = format!("fn g ( -- ) {locals} g end g");
+    assert_eq!(run(&source, Flags::default()), Err(VmError::StackOverflow));
+    assert_eq!(run(&source, noverify()), Err(VmError::StackOverflow));
+}
+
+#[test]
+fn faults_report_their_location() {
+    let source = "fn f ( int -- int )\n  0 /\nend\n1 f\ndrop";
+    let program = Program::compile(source, Some("a.roth"), &Flags::default(), &[]).unwrap();
+    let mut vm = program.runtime(Flags::default()).unwrap();
+    assert_eq!(vm.execute(), Err(VmError::DivisionByZero));
+    assert_eq!(vm.location().unwrap(), "a.roth:2:5 in :f");
+}
+
+#[test]
+fn checked_jumps_reject_targets_inside_instructions() {
+    let flags = Flags {
+        checked_jumps: true,
+        ..noverify()
+    };
+    // The target is the second byte of the instruction pushing it
+    assert_eq!(
+        run("1 ~addr jump", flags),
+        Err(VmError::InvalidJump { target: 1 })
+    );
+}
@@ -0,0 +1,102 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cacas::{ExitStatus, Flags, GcStats, Program, Signature, Type, VmError};
+
+/// Keeps every string in an array and every hundredth in a global while creating garbage, then
+/// checks all of them after many collections
+const SOURCE: &str = r#"
+var kept: [str]
+var log: str
+
+fn step ( int -- )
+    let i
+    "s" i ~str + let s
+    "garbage " s + drop
+    kept s push
+    i 100 mod 0 = if
+        log s + "," + log!
+    end
+end
+
+fn intact ( int -- bool )
+    let n
+    true let ok
+    0 let i
+    while i n < do
+        kept i get "s" i ~str + = ok and ok!
+        i 1 + i!
+    end
+    ok kept len n = and
+end
+
+0 while dup COUNT < do
+    dup step
+    1 +
+end drop
+gc
+log report
+COUNT intact if 0 else 1 end exit
+"#;
+
+#[test]
+fn strings_survive_collections() {
+    let count = if cfg!(miri) { 200 } else { 3000 };
+    let source = SOURCE.replace("COUNT", &count.to_string());
+    let signature = Signature {
+        inputs: vec![Type::String],
+        outputs: Vec::new(),
+    };
+    let natives = [("report".to_string(), signature.clone())];
+    let mut flags = Flags::default();
+    flags.heap.initial_size = 256;
+    flags.heap.growth_factor = 1.1;
+    let program = Program::compile(&source, None, &flags, &natives).unwrap();
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = program.runtime(flags).unwrap();
+    let sink = reported.clone();
+    vm.register("report", signature, move |stack| {
+        sink.borrow_mut().push(stack.pop_string()?);
+        Ok(())
+    });
+
+    assert_eq!(vm.execute(), Ok(ExitStatus::Exit(0)));
+    let expected: String = (0..count).step_by(100).map(|i| format!("s{i},")).collect();
+    assert_eq!(*reported.borrow(), [expected]);
+    assert!(vm.gc_stats().collections > 10);
+}
+
+/// Runs the source with a heap of at most 1000 bytes
+fn run_limited(source: &str) -> (Result<ExitStatus, VmError>, GcStats) {
+    let mut flags = Flags::default();
+    flags.heap.max_size = Some(1000);
+    let program = Program::compile(source, None, &flags, &[]).unwrap();
+    let mut vm = program.runtime(flags).unwrap();
+    (vm.execute(), vm.gc_stats())
+}
+
+#[test]
+fn empty_arrays_fill_the_heap() {
+    let (result, stats) = run_limited("0 while dup 1000 < do new-array int drop 1 + end drop");
+    assert_eq!(result, Ok(ExitStatus::Exit(0)));
+    assert!(stats.collections > 10);
+    // Arrays held by the callers can not be collected
+    let source = "fn f ( int -- ) dup 0 > if new-array int swap 1 - f drop else drop end end 100 f";
+    let (result, _) = run_limited(source);
+    assert!(
+        matches!(result, Err(VmError::OutOfMemory { .. })),
+        "{result:?}"
+    );
+}
+
+#[test]
+fn empty_strings_fill_the_heap() {
+    let (result, stats) = run_limited("0 while dup 1000 < do \"\" trim drop 1 + end drop");
+    assert_eq!(result, Ok(ExitStatus::Exit(0)));
+    assert!(stats.collections > 10);
+    let source = "fn f ( int -- ) dup 0 > if \"\" trim swap 1 - f drop else drop end end 100 f";
+    let (result, _) = run_limited(source);
+    assert!(
+        matches!(result, Err(VmError::OutOfMemory { .. })),
+        "{result:?}"
+    );
+}
@@ -0,0 +1,60 @@
+use cacas::{ExitStatus, Flags, NativeStack, Program, Signature, Type, VmError};
+
+/// Runs the source with a native `strlen ( str -- int )` implemented by the function
+fn run_strlen(
+    source: &str,
+    function: impl FnMut(&mut NativeStack) -> Result<(), String> + 'static,
+) -> Result<ExitStatus, VmError> {
+    let signature = Signature {
+        inputs: vec![Type::String],
+        outputs: vec![Type::Int],
+    };
+    let natives = [("strlen".to_string(), signature.clone())];
+    let program = Program::compile(source, None, &Flags::default(), &natives).unwrap();
+    let mut vm = program.runtime(Flags::default()).unwrap();
+    vm.register("strlen", signature, function);
+    vm.execute()
+}
+
+#[test]
+fn native_results_are_returned() {
+    let result = run_strlen("\"abc\" strlen 1 + exit", |stack| {
+        let string = stack.pop_string()?;
+        stack.push_int(string.len() as _)
+    });
+    assert_eq!(result, Ok(ExitStatus::Exit(4)));
+}
+
+#[test]
+fn pop_after_push_is_rejected() {
+    let result = run_strlen("\"abc\" strlen 1 + exit", |stack| {
+        stack.push_int(7)?;
+        stack.pop_string()?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(VmError::Native { .. })), "{result:?}");
+}
+
+#[test]
+fn natives_with_arrays_are_rejected() {
+    let signature = Signature {
+        inputs: vec![Type::array(Type::Int).unwrap()],
+        outputs: vec![Type::Int],
+    };
+    let natives = [("sum".to_string(), signature)];
+    let source = "new-array int sum exit";
+    assert!(Program::compile(source, None, &Flags::default(), &natives).is_err());
+}
+
+#[test]
+fn imports_are_listed() {
+    let signature = Signature {
+        inputs: vec![Type::String],
+        outputs: vec![Type::Int],
+    };
+    let natives = [("strlen".to_string(), signature)];
+    let program =
+        Program::compile("\"abc\" strlen exit", None, &Flags::default(), &natives).unwrap();
+    let vm = program.runtime(Flags::default()).unwrap();
+    assert_eq!(vm.imports(), &natives);
+}
@@ -0,0 +1,92 @@
+use cacas::{ExitStatus, Flags, Program, VmError};
+
+fn compile_error(source: &str) -> String {
+    match Program::compile(source, None, &Flags::default(), &[]) {
+        Ok(_) => panic!("{source:?} compiled"),
+        Err(diagnostic) => diagnostic.to_string(),
+    }
+}
+
+#[test]
+fn builtin_words_cannot_be_redefined() {
+    for source in [
+        "fn dup ( int -- int int ) 5 end",
+        "fn pick ( -- ) end",
+        "fn + ( -- ) end",
+    ] {
+        assert!(
+            compile_error(source).contains("Cannot redefine builtin word"),
+            "{source:?}"
+        );
+    }
+}
+
+#[test]
+fn builtin_words_cannot_name_variables() {
+    assert!(
+        compile_error("fn f ( int -- ) let print 7 print end").contains("Invalid name of local")
+    );
+    assert!(compile_error("var swap: int 1 2 swap print").contains("Invalid name of global"));
+}
+
+#[test]
+fn diagnostics_name_the_file() {
+    let Err(diagnostic) =
+        Program::compile("1\n  drop drop", Some("a.roth"), &Flags::default(), &[])
+    else {
+        panic!("compiled");
+    };
+    assert!(
+        diagnostic.to_string().starts_with("a.roth:2:8: "),
+        "{diagnostic}"
+    );
+}
+
+fn run(source: &str) -> Result<ExitStatus, VmError> {
+    let program = Program::compile(source, None, &Flags::default(), &[]).unwrap();
+    let mut vm = program.runtime(Flags::default()).unwrap();
+    vm.execute()
+}
+
+#[test]
+fn blocks_nest() {
+    let source = "
+fn classify ( int -- int )
+    let x
+    0 let sum
+    x 0 < if
+        -1
+    else
+        0 let i
+        while i x < do
+            i 2 mod 0 = if
+                sum i + sum!
+            else
+                i 3 = if sum 100 + sum! end
+            end
+            i 1 + i!
+        end
+        sum
+    end
+end
+-5 classify 6 classify + exit";
+    // -1 for the negative number, 0 + 2 + 4 of the even numbers and 100 for 3
+    assert_eq!(run(source), Ok(ExitStatus::Exit(105)));
+}
+
+#[test]
+fn blocks_have_to_be_balanced() {
+    assert!(compile_error("true if 1 drop end end").contains("Unexpected end"));
+    assert!(compile_error("1 while dup 0 > do 1 - end end").contains("Unexpected end"));
+    assert!(compile_error("true if 1 drop").contains("Missing end of if"));
+    assert!(compile_error("fn f ( -- ) true if end").contains("Missing end of word definition"));
+}
+
+#[test]
+fn conditional_jumps_need_label_addresses() {
+    assert_eq!(
+        run("true &skip if 1 exit :skip 2 exit"),
+        Ok(ExitStatus::Exit(2))
+    );
+    assert!(compile_error("true 0 ~addr if").contains("needs &label directly in front"));
+}
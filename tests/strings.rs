@@ -0,0 +1,42 @@
+use cacas::{ExitStatus, Flags, Program, VmError};
+
+fn run(source: &str) -> Result<ExitStatus, VmError> {
+    let program = Program::compile(source, None, &Flags::default(), &[]).unwrap();
+    let mut vm = program.runtime(Flags::default()).unwrap();
+    vm.execute()
+}
+
+/// Runs the source, which has to leave a bool on the stack
+fn check(source: &str) -> bool {
+    run(&format!("{source} if 1 else 0 end exit")) == Ok(ExitStatus::Exit(1))
+}
+
+#[test]
+fn indices_count_characters() {
+    assert_eq!(run("\"héllo\" len exit"), Ok(ExitStatus::Exit(5)));
+    assert!(check("\"héllo\" 1 char-at \"é\" ="));
+    assert!(check("\"héllo\" 1 3 substr \"éll\" ="));
+    assert!(check("\"héllo\" 4 1 substr \"o\" ="));
+    assert_eq!(run("\"héllo\" \"l\" find exit"), Ok(ExitStatus::Exit(2)));
+}
+
+#[test]
+fn find_without_occurrence_is_negative() {
+    assert_eq!(run("\"abc\" \"d\" find exit"), Ok(ExitStatus::Exit(-1)));
+    assert_eq!(run("\"\" \"a\" find exit"), Ok(ExitStatus::Exit(-1)));
+}
+
+#[test]
+fn indices_outside_of_strings_fault() {
+    for source in [
+        "\"abc\" 3 char-at drop",
+        "\"abc\" -1 char-at drop",
+        "\"héllo\" 4 2 substr drop",
+        "\"abc\" -1 1 substr drop",
+    ] {
+        assert!(
+            matches!(run(source), Err(VmError::IndexOutOfBounds { .. })),
+            "{source}"
+        );
+    }
+}